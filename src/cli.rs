@@ -0,0 +1,99 @@
+use std::{env, path::PathBuf, process};
+
+use crate::paths;
+
+const USAGE: &str = "Usage: gtav-race-gen-2-electric-boogaloo [OPTIONS]
+
+Options:
+    --config <FILE>     Read and save the config in FILE
+    --profile <NAME>    Use a separate config for the profile NAME
+    --data-dir <DIR>    Search DIR for map images before the XDG data directories
+    --fresh             Start without restoring the last session
+    -h, --help          Print this help";
+
+#[derive(Default)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub data_dir: Option<PathBuf>,
+    pub fresh: bool,
+}
+
+impl Args {
+    /// Parse the command line, exiting with usage information if it is invalid
+    pub fn parse() -> Self {
+        match Self::try_parse(env::args().skip(1)) {
+            Ok(args) => args,
+            Err(why) => {
+                eprintln!("{}\n\n{}", why, USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    fn try_parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+
+            match arg.as_str() {
+                "--config" => parsed.config = Some(value()?.into()),
+                "--profile" => {
+                    let profile = value()?;
+                    if !paths::valid_name(&profile) {
+                        return Err(format!(
+                            "Invalid profile name {:?}, it can't be empty or contain /, \\ or ..",
+                            profile
+                        ));
+                    }
+                    parsed.profile = Some(profile);
+                }
+                "--data-dir" => parsed.data_dir = Some(value()?.into()),
+                "--fresh" => parsed.fresh = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ => return Err(format!("Unknown argument {}", arg)),
+            }
+        }
+
+        if parsed.config.is_some() && parsed.profile.is_some() {
+            return Err("--config and --profile can't be used together".to_string());
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::try_parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn profile_names_are_checked() {
+        assert_eq!(
+            parse(&["--profile", "league"]).unwrap().profile.as_deref(),
+            Some("league")
+        );
+        for name in ["", " ", "../other", "a/b", "a\\b", ".."] {
+            assert!(
+                parse(&["--profile", name]).is_err(),
+                "{:?} was accepted",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn config_and_profile_conflict() {
+        assert!(parse(&["--config", "a.ron", "--profile", "league"]).is_err());
+        assert!(parse(&["--profile"]).is_err());
+        assert!(parse(&["--fresh"]).unwrap().fresh);
+    }
+}
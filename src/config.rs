@@ -0,0 +1,241 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    difficulty::DifficultyConfig, export::ExportConfig, history::FreshnessConfig,
+    lobby::LobbyConfig, maps::MapConfig, paths, results::ResultsConfig, roster::Roster,
+    style::Style, terrain::TerrainConfig, text_export::TextTemplates, webhook::WebhookConfig,
+    Point,
+};
+
+/// Version of the config layout written by this build
+const CONFIG_VERSION: u32 = 1;
+
+/// Steps bringing a config from version `i` to `i + 1`
+const MIGRATIONS: &[fn(&mut Config)] = &[
+    // 0 -> 1: the pool moved into per-map configs
+    |config| {
+        if config.maps.is_empty() {
+            config.maps.push(MapConfig::default());
+        }
+        if !config.race_points.is_empty() {
+            config.maps[0].race_points = std::mem::take(&mut config.race_points);
+        }
+    },
+];
+
+#[derive(Deserialize, Serialize)]
+pub struct Config {
+    /// Configs from before versioning have no version and count as 0
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    pub maps: Vec<MapConfig>,
+    #[serde(default)]
+    pub selected_map: usize,
+    /// Pool of version 0 configs, moved to the first map when migrating
+    #[serde(default, skip_serializing)]
+    race_points: Vec<Point>,
+    #[serde(default)]
+    pub style: Style,
+    #[serde(default)]
+    pub export: ExportConfig,
+    #[serde(default)]
+    pub text_templates: TextTemplates,
+    /// Continue where the last session left off on startup
+    #[serde(default = "default_restore_session")]
+    pub restore_session: bool,
+    #[serde(default)]
+    pub lobby: LobbyConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    #[serde(default)]
+    pub results: ResultsConfig,
+    #[serde(default)]
+    pub roster: Roster,
+    #[serde(default)]
+    pub freshness: FreshnessConfig,
+    #[serde(default)]
+    pub difficulty: DifficultyConfig,
+    #[serde(default)]
+    pub terrain: TerrainConfig,
+    /// Set when the file on disk couldn't be loaded or backed up, it isn't saved over then
+    #[serde(skip)]
+    read_only: bool,
+}
+
+fn default_restore_session() -> bool {
+    true
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            maps: vec![MapConfig::default()],
+            selected_map: 0,
+            race_points: Vec::new(),
+            style: Style::default(),
+            export: ExportConfig::default(),
+            text_templates: TextTemplates::default(),
+            restore_session: default_restore_session(),
+            lobby: LobbyConfig::default(),
+            webhook: WebhookConfig::default(),
+            results: ResultsConfig::default(),
+            roster: Roster::default(),
+            freshness: FreshnessConfig::default(),
+            difficulty: DifficultyConfig::default(),
+            terrain: TerrainConfig::default(),
+            read_only: false,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config at `path`, falling back to the defaults if there is none.
+    ///
+    /// Problems the user should know about are returned alongside the config. See
+    /// [`paths::load_ron`] for what happens with files that can't be loaded.
+    pub fn load(path: &Path) -> (Self, Option<String>) {
+        let (mut config, read_only, problem) = paths::load_ron::<Self>(path);
+        if let Some(problem) = problem {
+            config.read_only = read_only;
+            return (
+                config,
+                Some(format!("{}\nUsing the default config.", problem)),
+            );
+        }
+
+        let warning = (config.version > CONFIG_VERSION).then(|| {
+            format!(
+                "{} was written by a newer version, settings this version doesn't know about are lost when saving.",
+                path.display()
+            )
+        });
+
+        while (config.version as usize) < MIGRATIONS.len() {
+            MIGRATIONS[config.version as usize](&mut config);
+            config.version += 1;
+        }
+        config.version = CONFIG_VERSION;
+
+        if config.maps.is_empty() {
+            config.maps.push(MapConfig::default());
+        }
+        config.selected_map = config.selected_map.min(config.maps.len() - 1);
+
+        (config, warning)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if self.read_only {
+            return Err(format!(
+                "Not saving the config, {} couldn't be loaded",
+                path.display()
+            ));
+        }
+
+        let data = self.to_ron()?;
+        paths::write_atomic(path, data.as_bytes())
+            .map_err(|why| format!("Failed to save config to {}: {}", path.display(), why))
+    }
+
+    /// The config as it's written to its file
+    pub fn to_ron(&self) -> Result<String, String> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|why| format!("Failed to serialize config: {}", why))
+    }
+
+    /// Whether the file this was loaded from is kept from being saved over
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn map(&self) -> &MapConfig {
+        &self.maps[self.selected_map]
+    }
+
+    pub fn map_mut(&mut self) -> &mut MapConfig {
+        &mut self.maps[self.selected_map]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gtav-race-gen-config-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn unversioned_pool_moves_to_the_first_map() {
+        let dir = temp_dir("migrate");
+        let path = dir.join("config.ron");
+        fs::write(&path, "(race_points: [(x: 1.0, y: 2.0), (x: 3.0, y: 4.0)])").unwrap();
+
+        let (config, problem) = Config::load(&path);
+        assert!(problem.is_none());
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(config.race_points.is_empty());
+        assert!(config.maps[0].race_points == [Point::new(1.0, 2.0), Point::new(3.0, 4.0)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn newer_version_is_warned_about() {
+        let dir = temp_dir("newer");
+        let path = dir.join("config.ron");
+        fs::write(&path, format!("(version: {})", CONFIG_VERSION + 1)).unwrap();
+
+        let (config, problem) = Config::load(&path);
+        assert!(problem.is_some_and(|problem| problem.contains("newer version")));
+        assert!(!config.read_only());
+        assert_eq!(config.maps.len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broken_file_is_backed_up() {
+        let dir = temp_dir("broken");
+        let path = dir.join("config.ron");
+        fs::write(&path, "(maps: oops").unwrap();
+
+        let (config, problem) = Config::load(&path);
+        assert!(problem.is_some_and(|problem| problem.contains("A copy was saved")));
+        // With the copy made the defaults can be saved over it
+        assert!(!config.read_only());
+        config.save(&path).unwrap();
+        assert!(fs::read_dir(&dir).unwrap().any(|entry| entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .contains(".broken-")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_file_is_not_saved_over() {
+        let dir = temp_dir("unreadable");
+        let path = dir.join("config.ron");
+        fs::create_dir_all(&path).unwrap();
+
+        let (config, problem) = Config::load(&path);
+        assert!(problem.is_some());
+        assert!(config.read_only());
+        assert!(config.save(&path).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
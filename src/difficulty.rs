@@ -0,0 +1,286 @@
+use egui_macroquad::egui;
+use macroquad::math::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::{roads::RoadGraph, Point, Race};
+
+/// Races scoring below this are easy
+const EASY_MAX: f32 = 30.0;
+/// Races scoring below this are medium, above hard
+const MEDIUM_MAX: f32 = 60.0;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Difficulty {
+    #[default]
+    Any,
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    pub const ALL: &'static [Difficulty] = &[
+        Difficulty::Any,
+        Difficulty::Easy,
+        Difficulty::Medium,
+        Difficulty::Hard,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Any => "Any",
+            Difficulty::Easy => "Easy",
+            Difficulty::Medium => "Medium",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    /// Score generated races aim for
+    pub fn target(self) -> Option<f32> {
+        match self {
+            Difficulty::Any => None,
+            Difficulty::Easy => Some(EASY_MAX * 0.6),
+            Difficulty::Medium => Some((EASY_MAX + MEDIUM_MAX) / 2.0),
+            Difficulty::Hard => Some(MEDIUM_MAX * 1.3),
+        }
+    }
+
+    pub fn of(score: f32) -> Self {
+        if score < EASY_MAX {
+            Difficulty::Easy
+        } else if score < MEDIUM_MAX {
+            Difficulty::Medium
+        } else {
+            Difficulty::Hard
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DifficultyConfig {
+    /// How much harder a class makes a leg, classes not listed count as 1
+    pub class_factors: Vec<(String, f32)>,
+    /// Races generated to pick from when aiming for a difficulty
+    pub candidates: usize,
+}
+
+impl Default for DifficultyConfig {
+    fn default() -> Self {
+        Self {
+            class_factors: [
+                ("Compacts", 0.8),
+                ("Sedans", 0.8),
+                ("SUVs", 0.9),
+                ("Sports", 1.0),
+                ("Super", 1.1),
+                ("Muscle", 1.2),
+                ("Off-road", 1.2),
+                ("Motorcycles", 1.4),
+                ("Cycles", 1.6),
+            ]
+            .into_iter()
+            .map(|(class, factor)| (class.to_string(), factor))
+            .collect(),
+            candidates: 100,
+        }
+    }
+}
+
+impl DifficultyConfig {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.candidates, 10..=500).text("Candidates"))
+            .on_hover_text("Races compared when aiming for a difficulty");
+
+        let mut remove = None;
+        egui::Grid::new("class_factors").show(ui, |ui| {
+            for (i, (class, factor)) in self.class_factors.iter_mut().enumerate() {
+                ui.add(egui::TextEdit::singleline(class).desired_width(100.0));
+                ui.add(egui::Slider::new(factor, 0.5..=2.0));
+                if ui.small_button("x").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            self.class_factors.remove(i);
+        }
+        if ui.button("Add class").clicked() {
+            self.class_factors.push((String::new(), 1.0));
+        }
+    }
+
+    fn class_factor(&self, class: &str) -> f32 {
+        self.class_factors
+            .iter()
+            .find(|(other, _)| other.trim().eq_ignore_ascii_case(class.trim()))
+            .map(|(_, factor)| *factor)
+            .unwrap_or(1.0)
+    }
+}
+
+/// What a race's difficulty is made of, each part in points
+pub struct Score {
+    /// From the total driving distance
+    pub length: f32,
+    /// From how sharply the route turns at the checkpoints
+    pub turns: f32,
+    /// From the extra distance the tryhisuojaus add
+    pub detours: f32,
+    /// Multiplier from the classes, weighted by leg length
+    pub class_factor: f32,
+}
+
+impl Score {
+    pub fn total(&self) -> f32 {
+        (self.length + self.turns + self.detours) * self.class_factor
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "Length {:.0}, turns {:.0}, detours {:.0}, classes x{:.2}",
+            self.length, self.turns, self.detours, self.class_factor
+        )
+    }
+}
+
+pub fn score(
+    race: &Race,
+    meters_per_pixel: f32,
+    roads: Option<&RoadGraph>,
+    config: &DifficultyConfig,
+) -> Score {
+    let km = |pixels: f32| pixels * meters_per_pixel / 1000.0;
+
+    let length = km(race.length(roads));
+
+    // Every vertex of the route, detours included
+    let mut path: Vec<Point> = Vec::new();
+    for leg in 0..race.legs() {
+        let leg_path = race.leg_path(leg);
+        path.extend(if leg == 0 {
+            &leg_path[..]
+        } else {
+            &leg_path[1..]
+        });
+    }
+    let turns = path
+        .windows(3)
+        .map(|points| {
+            let a = Vec2::from(points[1]) - Vec2::from(points[0]);
+            let b = Vec2::from(points[2]) - Vec2::from(points[1]);
+            if a.length() == 0.0 || b.length() == 0.0 {
+                0.0
+            } else {
+                // 0 going straight on, 1 turning back
+                a.angle_between(b).abs() / std::f32::consts::PI
+            }
+        })
+        .sum::<f32>();
+
+    let detours = (0..race.legs())
+        .map(|leg| {
+            let direct =
+                crate::leg_distance(race.checkpoints[leg].0, race.checkpoints[leg + 1].0, roads);
+            km(race.leg_length(leg, roads) - direct)
+        })
+        .sum::<f32>();
+
+    let class_factor = if race.length(roads) > 0.0 {
+        (0..race.legs())
+            .map(|leg| {
+                let class = race.checkpoints[leg].1.as_deref().unwrap_or_default();
+                config.class_factor(class) * race.leg_length(leg, roads)
+            })
+            .sum::<f32>()
+            / race.length(roads)
+    } else {
+        1.0
+    };
+
+    Score {
+        length: length * 2.0,
+        turns: turns * 8.0,
+        detours: detours * 6.0,
+        class_factor,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn race(points: &[(f32, f32)], classes: &[&str]) -> Race {
+        let points = points
+            .iter()
+            .map(|(x, y)| Point::new(*x, *y))
+            .collect::<Vec<_>>();
+        let classes = classes
+            .iter()
+            .map(|class| class.to_string())
+            .collect::<Vec<_>>();
+        Race::new(&points, &[], &classes, 0)
+    }
+
+    #[test]
+    fn scores_fall_into_bands() {
+        assert!(Difficulty::of(0.0) == Difficulty::Easy);
+        assert!(Difficulty::of(EASY_MAX - 0.1) == Difficulty::Easy);
+        assert!(Difficulty::of(EASY_MAX) == Difficulty::Medium);
+        assert!(Difficulty::of(MEDIUM_MAX) == Difficulty::Hard);
+
+        assert!(Difficulty::Any.target().is_none());
+        // Aiming for a difficulty lands in its band
+        for difficulty in &Difficulty::ALL[1..] {
+            assert!(Difficulty::of(difficulty.target().unwrap()) == *difficulty);
+        }
+    }
+
+    #[test]
+    fn straight_race_scores_its_length() {
+        // 1 px is 1 m, so 2 km straight on
+        let race = race(
+            &[(0.0, 0.0), (1000.0, 0.0), (2000.0, 0.0)],
+            &["Sports", "?"],
+        );
+        let score = score(&race, 1.0, None, &DifficultyConfig::default());
+
+        assert_eq!(score.length, 4.0);
+        assert_eq!(score.turns, 0.0);
+        assert_eq!(score.detours, 0.0);
+        assert_eq!(score.class_factor, 1.0);
+        assert_eq!(score.total(), 4.0);
+    }
+
+    #[test]
+    fn turning_back_and_detours_add_up() {
+        let mut race = race(
+            &[(0.0, 0.0), (1000.0, 0.0), (0.0, 0.0)],
+            &["Sports", "Sports"],
+        );
+        let config = DifficultyConfig::default();
+        let score_before = score(&race, 1.0, None, &config);
+        // A full turn back at the middle checkpoint
+        assert!((score_before.turns - 8.0).abs() < 0.001);
+
+        // 500 m out of the way and back on the first leg
+        race.tryhisuojaus = vec![(0, Point::new(500.0, 500.0))];
+        let score_after = score(&race, 1.0, None, &config);
+        let detour = 2.0 * 500.0 * 2f32.sqrt() / 1000.0 - 1.0;
+        assert!((score_after.detours - detour * 6.0).abs() < 0.001);
+        assert!(score_after.total() > score_before.total());
+    }
+
+    #[test]
+    fn classes_weigh_by_leg_length() {
+        let race = race(
+            &[(0.0, 0.0), (1000.0, 0.0), (2000.0, 0.0)],
+            &["Motorcycles", "compacts "],
+        );
+        let score = score(&race, 1.0, None, &DifficultyConfig::default());
+
+        // 1.4 and 0.8 over legs of the same length, matched regardless of case and spaces
+        assert!((score.class_factor - 1.1).abs() < 0.001);
+    }
+}
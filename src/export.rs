@@ -0,0 +1,388 @@
+use std::{env, fs, path::PathBuf};
+
+use egui_macroquad::egui;
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    DynamicImage, RgbaImage,
+};
+use macroquad::math::{Rect, Vec2};
+use serde::{Deserialize, Serialize};
+
+/// Lowest JPEG quality tried before resorting to downscaling when fitting a size budget
+const MIN_JPEG_QUALITY: u8 = 40;
+/// Images are never downscaled narrower than this when fitting a size budget
+const MIN_WIDTH: u32 = 300;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl ImageFormat {
+    pub const ALL: &'static [ImageFormat] =
+        &[ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "PNG",
+            ImageFormat::Jpeg => "JPEG",
+            ImageFormat::WebP => "WebP",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::WebP => "image/webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::WebP => "webp",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum AspectRatio {
+    Free,
+    Wide,
+    Square,
+}
+
+impl AspectRatio {
+    pub const ALL: &'static [AspectRatio] =
+        &[AspectRatio::Free, AspectRatio::Wide, AspectRatio::Square];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AspectRatio::Free => "Free",
+            AspectRatio::Wide => "16:9",
+            AspectRatio::Square => "Square",
+        }
+    }
+
+    /// Width divided by height
+    fn ratio(self) -> Option<f32> {
+        match self {
+            AspectRatio::Free => None,
+            AspectRatio::Wide => Some(16.0 / 9.0),
+            AspectRatio::Square => Some(1.0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct ExportConfig {
+    pub directory: String,
+    /// File name without extension, `{date}`, `{time}` and `{seed}` are replaced
+    pub filename_template: String,
+    pub format: ImageFormat,
+    pub jpeg_quality: u8,
+    /// Maximum file size in kilobytes, quality and resolution are lowered to fit. WebP is
+    /// always lossless, so only the resolution is.
+    pub size_budget_kb: Option<u32>,
+    /// Only export the part of the map around the route
+    pub crop_to_route: bool,
+    /// Space left around the route when cropping, in map pixels
+    pub crop_padding: f32,
+    pub aspect_ratio: AspectRatio,
+    /// Resolution multiplier relative to the map image, text and lines are scaled along
+    pub scale: u32,
+    /// Draw a summary of the legs, distances and seed into the image
+    pub legend: bool,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            directory: env::var("HOME")
+                .map(|home| format!("{}/Pictures", home))
+                .unwrap_or_else(|_| ".".to_string()),
+            filename_template: "race-{date}-{time}-{seed}".to_string(),
+            format: ImageFormat::Png,
+            jpeg_quality: 90,
+            size_budget_kb: None,
+            crop_to_route: false,
+            crop_padding: 150.0,
+            aspect_ratio: AspectRatio::Free,
+            scale: 1,
+            legend: false,
+        }
+    }
+}
+
+impl ExportConfig {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("export_settings").show(ui, |ui| {
+            ui.label("Directory");
+            ui.text_edit_singleline(&mut self.directory);
+            ui.end_row();
+
+            ui.label("File name");
+            ui.text_edit_singleline(&mut self.filename_template)
+                .on_hover_text("{date}, {time} and {seed} are replaced");
+            ui.end_row();
+
+            ui.label("Format");
+            egui::ComboBox::from_id_source("export_format")
+                .selected_text(self.format.name())
+                .show_ui(ui, |ui| {
+                    for format in ImageFormat::ALL {
+                        ui.selectable_value(&mut self.format, *format, format.name());
+                    }
+                });
+            ui.end_row();
+
+            if self.format == ImageFormat::Jpeg {
+                ui.label("JPEG quality");
+                ui.add(egui::Slider::new(&mut self.jpeg_quality, 1..=100));
+                ui.end_row();
+            }
+
+            let mut limit_size = self.size_budget_kb.is_some();
+            ui.checkbox(&mut limit_size, "Size limit");
+            match (limit_size, &mut self.size_budget_kb) {
+                (true, Some(budget)) => {
+                    ui.add(
+                        egui::DragValue::new(budget)
+                            .suffix(" KB")
+                            .clamp_range(50..=100_000),
+                    );
+                }
+                (true, None) => self.size_budget_kb = Some(8 * 1024),
+                (false, _) => self.size_budget_kb = None,
+            }
+            ui.end_row();
+
+            if limit_size && self.format == ImageFormat::WebP {
+                ui.label("");
+                ui.label("WebP is saved lossless, so it's scaled down to fit the limit");
+                ui.end_row();
+            }
+
+            ui.label("Resolution");
+            ui.horizontal(|ui| {
+                for scale in [1, 2, 4] {
+                    ui.selectable_value(&mut self.scale, scale, format!("{}x", scale));
+                }
+            });
+            ui.end_row();
+
+            ui.checkbox(&mut self.crop_to_route, "Crop to route");
+            if self.crop_to_route {
+                ui.add(egui::Slider::new(&mut self.crop_padding, 0.0..=500.0).text("Padding"));
+            }
+            ui.end_row();
+
+            ui.label("Aspect ratio");
+            egui::ComboBox::from_id_source("export_aspect_ratio")
+                .selected_text(self.aspect_ratio.name())
+                .show_ui(ui, |ui| {
+                    for aspect_ratio in AspectRatio::ALL {
+                        ui.selectable_value(
+                            &mut self.aspect_ratio,
+                            *aspect_ratio,
+                            aspect_ratio.name(),
+                        );
+                    }
+                });
+            ui.end_row();
+
+            ui.checkbox(&mut self.legend, "Legend");
+            ui.end_row();
+        });
+    }
+
+    /// Part of the map to export, in map pixels. When cropping it covers everything in
+    /// `contents`, the checkpoints and their labels, plus the padding.
+    pub fn region(&self, map_size: Vec2, contents: impl IntoIterator<Item = Rect>) -> Rect {
+        let map = Rect::new(0.0, 0.0, map_size.x, map_size.y);
+
+        let mut contents = contents.into_iter();
+        let mut region = match (self.crop_to_route, contents.next()) {
+            (true, Some(first)) => {
+                let (min, max) = contents.fold(
+                    (first.point(), first.point() + first.size()),
+                    |(min, max), rect| (min.min(rect.point()), max.max(rect.point() + rect.size())),
+                );
+                let padding = Vec2::splat(self.crop_padding);
+                let (min, max) = (min - padding, max + padding);
+                Rect::new(min.x, min.y, max.x - min.x, max.y - min.y)
+            }
+            _ => map,
+        };
+
+        // Grow the short side around the center to reach the aspect ratio
+        if let Some(ratio) = self.aspect_ratio.ratio() {
+            let center = region.center();
+            let (w, h) = if region.w / region.h.max(1.0) < ratio {
+                (region.h * ratio, region.h)
+            } else {
+                (region.w, region.w / ratio)
+            };
+            region = Rect::new(center.x - w / 2.0, center.y - h / 2.0, w, h);
+        }
+
+        // Keep as much of the region on the map as possible
+        if region.w <= map.w {
+            region.x = region.x.clamp(0.0, map.w - region.w);
+        }
+        if region.h <= map.h {
+            region.y = region.y.clamp(0.0, map.h - region.h);
+        }
+
+        region
+    }
+
+    /// Path for a new image, with the template filled in. Races of a series get their number
+    /// appended, so a template without `{seed}` doesn't write them all to the same file.
+    pub fn path(&self, seed: u64, race: Option<usize>) -> PathBuf {
+        let now = chrono::Local::now();
+        let filename = self
+            .filename_template
+            .replace("{date}", &now.format("%Y-%m-%d").to_string())
+            .replace("{time}", &now.format("%H-%M-%S").to_string())
+            .replace("{seed}", &seed.to_string());
+        let filename = match race {
+            Some(race) => format!("{}-{}", filename, race),
+            None => filename,
+        };
+
+        PathBuf::from(&self.directory).join(format!("{}.{}", filename, self.format.extension()))
+    }
+
+    /// Encode and write the image, returning the path it was written to
+    pub fn save(
+        &self,
+        image: RgbaImage,
+        seed: u64,
+        race: Option<usize>,
+    ) -> Result<PathBuf, String> {
+        let data = self.encode(image)?;
+        let path = self.path(seed, race);
+
+        fs::create_dir_all(&self.directory)
+            .map_err(|why| format!("Failed to create {}: {}", self.directory, why))?;
+        fs::write(&path, data)
+            .map_err(|why| format!("Failed to write {}: {}", path.display(), why))?;
+
+        Ok(path)
+    }
+
+    /// Encode the image in the configured format, staying under the size budget if there is one.
+    /// JPEG quality is lowered first, PNG and WebP are lossless and can only be downscaled.
+    pub fn encode(&self, mut image: RgbaImage) -> Result<Vec<u8>, String> {
+        let mut quality = self.jpeg_quality;
+
+        loop {
+            let data = encode(&image, self.format, quality)?;
+
+            let budget = match self.size_budget_kb {
+                Some(budget) => budget as usize * 1024,
+                None => return Ok(data),
+            };
+            if data.len() <= budget {
+                return Ok(data);
+            }
+
+            if self.format == ImageFormat::Jpeg && quality > MIN_JPEG_QUALITY {
+                quality = quality.saturating_sub(10).max(MIN_JPEG_QUALITY);
+            } else if image.width() > MIN_WIDTH {
+                // Shrink roughly proportionally to how far over the budget we are
+                let ratio = ((budget as f32 / data.len() as f32).sqrt() * 0.95).clamp(0.5, 0.9);
+                image = image::imageops::resize(
+                    &image,
+                    (image.width() as f32 * ratio) as u32,
+                    (image.height() as f32 * ratio) as u32,
+                    FilterType::Triangle,
+                );
+            } else {
+                return Err(format!(
+                    "Could not fit the image in {} KB",
+                    self.size_budget_kb.unwrap()
+                ));
+            }
+        }
+    }
+}
+
+fn encode(image: &RgbaImage, format: ImageFormat, jpeg_quality: u8) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+
+    let result = match format {
+        ImageFormat::Png => image.write_with_encoder(PngEncoder::new(&mut data)),
+        ImageFormat::Jpeg => DynamicImage::ImageRgba8(image.clone())
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, jpeg_quality)),
+        ImageFormat::WebP => image.write_with_encoder(WebPEncoder::new_lossless(&mut data)),
+    };
+
+    result
+        .map(|_| data)
+        .map_err(|why| format!("Failed to encode {}: {}", format.name(), why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP_SIZE: Vec2 = Vec2::new(1000.0, 800.0);
+
+    fn cropped(aspect_ratio: AspectRatio) -> ExportConfig {
+        ExportConfig {
+            crop_to_route: true,
+            crop_padding: 10.0,
+            aspect_ratio,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn region_covers_labels_and_padding() {
+        let contents = [
+            Rect::new(100.0, 100.0, 0.0, 0.0),
+            // A label sticking out to the right of the last checkpoint
+            Rect::new(300.0, 200.0, 150.0, 30.0),
+        ];
+
+        let region = cropped(AspectRatio::Free).region(MAP_SIZE, contents);
+        assert_eq!(region, Rect::new(90.0, 90.0, 370.0, 150.0));
+
+        // Without cropping the whole map is exported
+        let region = ExportConfig::default().region(MAP_SIZE, contents);
+        assert_eq!(region, Rect::new(0.0, 0.0, 1000.0, 800.0));
+    }
+
+    #[test]
+    fn region_keeps_aspect_ratio_on_the_map() {
+        let contents = [Rect::new(0.0, 0.0, 100.0, 100.0)];
+
+        let region = cropped(AspectRatio::Wide).region(MAP_SIZE, contents);
+        assert!((region.w / region.h - 16.0 / 9.0).abs() < 0.001);
+        assert!((region.h - 120.0).abs() < 0.001);
+        // Pushed back onto the map from the corner
+        assert_eq!(region.point(), Vec2::ZERO);
+    }
+
+    #[test]
+    fn series_races_get_their_own_files() {
+        let export = ExportConfig {
+            directory: "images".to_string(),
+            filename_template: "race".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(export.path(7, None), PathBuf::from("images/race.png"));
+        assert_eq!(export.path(7, Some(1)), PathBuf::from("images/race-1.png"));
+        assert_ne!(export.path(7, Some(1)), export.path(7, Some(2)));
+    }
+}
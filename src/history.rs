@@ -0,0 +1,258 @@
+use std::path::Path;
+
+use chrono::{DateTime, Local};
+use egui_macroquad::egui;
+use macroquad::rand;
+use serde::{Deserialize, Serialize};
+
+use crate::{paths, Point, Race};
+
+/// Races kept in the history, older ones are forgotten
+const MAX_ENTRIES: usize = 200;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum FreshnessMode {
+    /// Every point is as likely to be picked
+    Off,
+    /// Recently used points are less likely to be picked
+    DownWeight,
+    /// Recently used points are only picked when the pool runs out
+    Exclude,
+}
+
+impl FreshnessMode {
+    pub const ALL: &'static [FreshnessMode] = &[
+        FreshnessMode::Off,
+        FreshnessMode::DownWeight,
+        FreshnessMode::Exclude,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FreshnessMode::Off => "Off",
+            FreshnessMode::DownWeight => "Prefer fresh points",
+            FreshnessMode::Exclude => "Avoid recent points",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FreshnessConfig {
+    pub mode: FreshnessMode,
+    /// How many of the latest races count as recent
+    pub window: usize,
+}
+
+impl Default for FreshnessConfig {
+    fn default() -> Self {
+        Self {
+            mode: FreshnessMode::DownWeight,
+            window: 5,
+        }
+    }
+}
+
+impl FreshnessConfig {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Checkpoint freshness")
+            .selected_text(self.mode.name())
+            .show_ui(ui, |ui| {
+                for mode in FreshnessMode::ALL {
+                    ui.selectable_value(&mut self.mode, *mode, mode.name());
+                }
+            });
+        if self.mode != FreshnessMode::Off {
+            ui.add(egui::Slider::new(&mut self.window, 1..=20).text("Recent races"));
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    /// Name of the map the race was made on
+    pub map: String,
+    pub generated_at: DateTime<Local>,
+    pub points: Vec<Point>,
+}
+
+/// Points of the races generated so far, newest last
+#[derive(Serialize, Deserialize, Default)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+    /// Set when the file on disk couldn't be loaded or backed up, it isn't saved over then
+    #[serde(skip)]
+    read_only: bool,
+}
+
+impl History {
+    /// Load the history, a missing file is an empty history. See [`paths::load_ron`] for what
+    /// happens with files that can't be loaded.
+    pub fn load(path: &Path) -> (Self, Option<String>) {
+        let (mut history, read_only, problem) = paths::load_ron::<Self>(path);
+        history.read_only = read_only;
+        (history, problem)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if self.read_only {
+            return Err(format!(
+                "Not saving the history, {} couldn't be loaded",
+                path.display()
+            ));
+        }
+
+        let data = ron::ser::to_string(self)
+            .map_err(|why| format!("Failed to serialize history: {}", why))?;
+
+        paths::write_atomic(path, data.as_bytes())
+            .map_err(|why| format!("Failed to save history to {}: {}", path.display(), why))
+    }
+
+    pub fn record(&mut self, race: &Race, map: &str) {
+        self.entries.push(HistoryEntry {
+            map: map.to_string(),
+            generated_at: race.generated_at,
+            points: race
+                .checkpoints
+                .iter()
+                .map(|(point, _)| *point)
+                .chain(race.tryhisuojaus.iter().map(|(_, point)| *point))
+                .collect(),
+        });
+
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.drain(..self.entries.len() - MAX_ENTRIES);
+        }
+    }
+
+    /// How many races ago `point` was last used on `map`, 0 being the latest race.
+    /// `None` if it wasn't used in the last `window` races.
+    pub fn age(&self, map: &str, point: Point, window: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.map == map)
+            .take(window)
+            .position(|entry| entry.points.contains(&point))
+    }
+
+    /// Chance of each point of `pool` to be picked, relative to the others
+    pub fn weights(&self, map: &str, pool: &[Point], config: &FreshnessConfig) -> Vec<f32> {
+        pool.iter()
+            .map(
+                |point| match (config.mode, self.age(map, *point, config.window)) {
+                    (FreshnessMode::Off, _) | (_, None) => 1.0,
+                    // Grows back towards 1 as the point gets older
+                    (FreshnessMode::DownWeight, Some(age)) => {
+                        (age + 1) as f32 / (config.window + 1) as f32 * 0.5
+                    }
+                    // Only used once the fresh points run out, oldest first
+                    (FreshnessMode::Exclude, Some(age)) => {
+                        1e-6 * (age + 1) as f32 / (config.window + 1) as f32
+                    }
+                },
+            )
+            .collect()
+    }
+}
+
+/// Pick `count` different points, each with a chance proportional to its weight
+pub fn pick_weighted(pool: &[Point], weights: &[f32], count: usize) -> Vec<Point> {
+    let mut candidates = pool
+        .iter()
+        .copied()
+        .zip(weights.iter().copied())
+        .collect::<Vec<_>>();
+    let mut picked = Vec::with_capacity(count);
+
+    while picked.len() < count && !candidates.is_empty() {
+        let total = candidates.iter().map(|(_, weight)| weight).sum::<f32>();
+        let mut target = rand::gen_range(0.0, total);
+
+        let index = candidates
+            .iter()
+            .position(|(_, weight)| {
+                target -= weight;
+                target <= 0.0
+            })
+            .unwrap_or(candidates.len() - 1);
+
+        picked.push(candidates.swap_remove(index).0);
+    }
+
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn history(races: &[&[Point]]) -> History {
+        History {
+            entries: races
+                .iter()
+                .map(|points| HistoryEntry {
+                    map: "Map".to_string(),
+                    generated_at: Local::now(),
+                    points: points.to_vec(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn age_counts_back_from_latest_race() {
+        let a = Point::new(1.0, 1.0);
+        let b = Point::new(2.0, 2.0);
+        let history = history(&[&[a], &[b], &[]]);
+
+        assert_eq!(history.age("Map", b, 5), Some(1));
+        assert_eq!(history.age("Map", a, 5), Some(2));
+        assert_eq!(history.age("Map", a, 2), None);
+        assert_eq!(history.age("Other map", a, 5), None);
+    }
+
+    #[test]
+    fn exclude_picks_fresh_points_first() {
+        let used = Point::new(1.0, 1.0);
+        let fresh = Point::new(2.0, 2.0);
+        let history = history(&[&[used]]);
+        let config = FreshnessConfig {
+            mode: FreshnessMode::Exclude,
+            window: 5,
+        };
+        let pool = [used, fresh];
+        let weights = history.weights("Map", &pool, &config);
+
+        rand::srand(1);
+        for _ in 0..20 {
+            assert!(pick_weighted(&pool, &weights, 1) == [fresh]);
+        }
+        // Recent points still come up once the fresh ones run out
+        assert_eq!(pick_weighted(&pool, &weights, 3).len(), 2);
+    }
+
+    #[test]
+    fn broken_file_is_backed_up() {
+        let dir =
+            std::env::temp_dir().join(format!("gtav-race-gen-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.ron");
+        fs::write(&path, "not a history").unwrap();
+
+        let (history, error) = History::load(&path);
+        assert!(error.is_some());
+        assert!(history.entries.is_empty());
+        assert!(fs::read_dir(&dir).unwrap().any(|entry| entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with("history.ron.broken-")));
+
+        history.save(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
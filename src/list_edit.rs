@@ -0,0 +1,74 @@
+//! Text fields editing a list as comma separated text.
+
+use std::{hash::Hash, str::FromStr};
+
+use egui_macroquad::egui;
+
+/// Edit `items` as comma separated text.
+///
+/// The text is kept as typed while the field has focus and only parsed once it loses it, so a
+/// separator can be typed before the next item. Items that don't parse are dropped. Returns
+/// true if `items` changed.
+pub fn comma_separated<T: ToString + FromStr + PartialEq>(
+    ui: &mut egui::Ui,
+    id_source: impl Hash,
+    items: &mut Vec<T>,
+    hover_text: &str,
+) -> bool {
+    let id = ui.make_persistent_id(id_source);
+    let mut text = ui
+        .data_mut(|data| data.get_temp::<String>(id))
+        .unwrap_or_else(|| join(items));
+
+    let response = ui.text_edit_singleline(&mut text).on_hover_text(hover_text);
+
+    if response.has_focus() {
+        ui.data_mut(|data| data.insert_temp(id, text));
+        return false;
+    }
+    ui.data_mut(|data| data.remove::<String>(id));
+
+    if !response.lost_focus() {
+        return false;
+    }
+    let parsed = parse(&text);
+    let changed = parsed != *items;
+    *items = parsed;
+    changed
+}
+
+fn join<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn parse<T: FromStr>(text: &str) -> Vec<T> {
+    text.split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .filter_map(|item| item.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_skips_empty_and_invalid_items() {
+        assert_eq!(parse::<u32>("10, 8,, 6 ,x, "), vec![10, 8, 6]);
+        assert_eq!(
+            parse::<String>(" Adder,Zentorno , "),
+            vec!["Adder".to_string(), "Zentorno".to_string()]
+        );
+    }
+
+    #[test]
+    fn join_parses_back() {
+        let items = vec![25, 18, 15];
+        assert_eq!(parse::<u32>(&join(&items)), items);
+    }
+}
@@ -0,0 +1,434 @@
+//! Sharing the current race with friends on the same network.
+//!
+//! The host runs a small HTTP server serving the race as `/race.json` and the rendered map as
+//! `/race.png`, guests poll the JSON and show the race on their own map.
+
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Cursor,
+    net::{IpAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Response, Server};
+
+use crate::{export::ExportConfig, Race};
+
+/// How often guests ask the host for a new race
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long the race has to stay unchanged before it's published, so editing a class doesn't
+/// render the map on every keystroke
+const PUBLISH_DELAY: Duration = Duration::from_millis(500);
+/// Addresses tried for finding the local network interface, in case there's no route to the
+/// internet. No packets are sent to them.
+const ROUTE_PROBES: &[&str] = &[
+    "8.8.8.8:80",
+    "192.168.0.1:80",
+    "192.168.1.1:80",
+    "10.0.0.1:80",
+    "172.16.0.1:80",
+];
+
+/// Page for joining from a browser, reloads the image every few seconds
+const INDEX_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>GTAV Race Gen</title><meta http-equiv="refresh" content="5"></head>
+<body style="margin: 0; background: black">
+<img src="/race.png" style="max-width: 100%" alt="No race yet">
+</body>
+</html>
+"#;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LobbyConfig {
+    /// Port the server listens on when hosting
+    pub port: u16,
+    /// Last address joined, kept so rejoining is one click
+    pub host_address: String,
+}
+
+impl Default for LobbyConfig {
+    fn default() -> Self {
+        Self {
+            port: 7878,
+            host_address: String::new(),
+        }
+    }
+}
+
+/// What the host serves at `/race.json`
+#[derive(Serialize, Deserialize)]
+pub struct RaceUpdate {
+    /// Increased every time the host publishes a different race
+    pub revision: u64,
+    /// Name of the map the race was made on
+    pub map: String,
+    pub race: Race,
+}
+
+#[derive(Default)]
+struct Published {
+    json: Option<String>,
+    image: Option<Arc<image::RgbaImage>>,
+    /// Encoded `image`, made by the server thread on the first request for it
+    png: Option<Vec<u8>>,
+}
+
+pub struct Host {
+    pub port: u16,
+    server: Arc<Server>,
+    published: Arc<Mutex<Published>>,
+    thread: Option<JoinHandle<()>>,
+    /// Hash of the race last published, to tell when it changes
+    race_hash: Option<u64>,
+    /// Hash of a changed race and when it was first seen, it's published once it settles
+    pending: Option<(u64, Instant)>,
+    revision: u64,
+}
+
+impl Host {
+    pub fn start(port: u16) -> Result<Self, String> {
+        let server = Arc::new(
+            Server::http(("0.0.0.0", port))
+                .map_err(|why| format!("Could not listen on port {}: {}", port, why))?,
+        );
+        // Port 0 lets the system pick one
+        let port = server
+            .server_addr()
+            .to_ip()
+            .map(|address| address.port())
+            .unwrap_or(port);
+        let published = Arc::new(Mutex::new(Published::default()));
+
+        let thread = {
+            let server = server.clone();
+            let published = published.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    if request.url() == "/race.png" {
+                        encode_published(&published);
+                    }
+
+                    let published = published.lock().unwrap();
+                    let (body, content_type) = match request.url() {
+                        "/" => (Some(INDEX_PAGE.as_bytes().to_vec()), "text/html"),
+                        "/race.json" => (
+                            published.json.as_ref().map(|json| json.as_bytes().to_vec()),
+                            "application/json",
+                        ),
+                        "/race.png" => (published.png.clone(), "image/png"),
+                        _ => (None, "text/plain"),
+                    };
+                    drop(published);
+
+                    // Failing to answer just means the guest tries again
+                    let _ = match body {
+                        Some(body) => request.respond(Response::from_data(body).with_header(
+                            Header::from_bytes("Content-Type", content_type).unwrap(),
+                        )),
+                        None => request
+                            .respond(Response::from_string("No race here").with_status_code(404)),
+                    };
+                }
+            })
+        };
+
+        Ok(Self {
+            port,
+            server,
+            published,
+            thread: Some(thread),
+            race_hash: None,
+            pending: None,
+            // Start from the clock so guests notice a restarted host
+            revision: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_millis() as u64)
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Address guests on the network can join, if one can be found
+    pub fn address(&self) -> Option<String> {
+        local_ip().map(|ip| format!("{}:{}", ip, self.port))
+    }
+
+    /// Whether `race` differs from the one published last and has stayed the same for a moment
+    pub fn needs_update(&mut self, race: Option<&Race>) -> bool {
+        let hash = race_hash(race);
+        if self.race_hash == Some(hash) {
+            self.pending = None;
+            return false;
+        }
+
+        match self.pending {
+            Some((pending, since)) if pending == hash => since.elapsed() >= PUBLISH_DELAY,
+            _ => {
+                self.pending = Some((hash, Instant::now()));
+                false
+            }
+        }
+    }
+
+    /// Serve `race` to guests, with `image` of it for browsers
+    pub fn publish(&mut self, race: Option<&Race>, map: &str, image: Option<image::RgbaImage>) {
+        self.race_hash = Some(race_hash(race));
+        self.pending = None;
+        self.revision += 1;
+
+        let json = race.and_then(|race| {
+            serde_json::to_string(&RaceUpdate {
+                revision: self.revision,
+                map: map.to_string(),
+                race: race.clone(),
+            })
+            .ok()
+        });
+
+        *self.published.lock().unwrap() = Published {
+            json,
+            image: image.map(Arc::new),
+            png: None,
+        };
+    }
+}
+
+impl Drop for Host {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct Guest {
+    pub address: String,
+    updates: Receiver<Result<RaceUpdate, String>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Guest {
+    /// Start following the race of the host at `address`, `host:port` or a full URL
+    pub fn join(address: &str) -> Self {
+        let address = address.trim().trim_end_matches('/').to_string();
+        let url = if address.contains("://") {
+            format!("{}/race.json", address)
+        } else {
+            format!("http://{}/race.json", address)
+        };
+
+        let (sender, updates) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        {
+            let stop = stop.clone();
+            let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+            thread::spawn(move || {
+                let mut revision = None;
+                let mut failing = false;
+
+                while !stop.load(Ordering::Relaxed) {
+                    let update = match agent.get(&url).call() {
+                        Ok(response) => response
+                            .into_json::<RaceUpdate>()
+                            .map(Some)
+                            .map_err(|why| format!("Host sent an invalid race: {}", why)),
+                        // The host has no race yet
+                        Err(ureq::Error::Status(404, _)) => Ok(None),
+                        Err(why) => Err(format!("Could not reach host: {}", why)),
+                    };
+
+                    let message = match update {
+                        Ok(Some(update)) if Some(update.revision) != revision => {
+                            revision = Some(update.revision);
+                            failing = false;
+                            Some(Ok(update))
+                        }
+                        Ok(_) => {
+                            failing = false;
+                            None
+                        }
+                        // Only report the first of a row of failures
+                        Err(why) if !failing => {
+                            failing = true;
+                            Some(Err(why))
+                        }
+                        Err(_) => None,
+                    };
+
+                    if let Some(message) = message {
+                        if sender.send(message).is_err() {
+                            return;
+                        }
+                    }
+
+                    thread::sleep(POLL_INTERVAL);
+                }
+            });
+        }
+
+        Self {
+            address,
+            updates,
+            stop,
+        }
+    }
+
+    /// Latest news from the host since the last call
+    pub fn poll(&self) -> Option<Result<RaceUpdate, String>> {
+        self.updates.try_iter().last()
+    }
+}
+
+impl Drop for Guest {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Settings the map served to browsers is rendered with, the host's export settings are for
+/// saved images
+pub fn image_settings() -> ExportConfig {
+    ExportConfig {
+        scale: 1,
+        crop_to_route: false,
+        legend: false,
+        ..Default::default()
+    }
+}
+
+/// Encode the published image if that hasn't been done yet, without holding the lock meanwhile
+fn encode_published(published: &Mutex<Published>) {
+    let image = {
+        let published = published.lock().unwrap();
+        match (&published.image, &published.png) {
+            (Some(image), None) => image.clone(),
+            _ => return,
+        }
+    };
+
+    let png = encode_png(&image);
+
+    let mut published = published.lock().unwrap();
+    // Unless a newer race was published in the meantime
+    if published
+        .image
+        .as_ref()
+        .is_some_and(|current| Arc::ptr_eq(current, &image))
+    {
+        published.png = png;
+    }
+}
+
+/// Encode an image for serving at `/race.png`
+fn encode_png(image: &image::RgbaImage) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)
+        .ok()
+        .map(|_| data)
+}
+
+fn race_hash(race: Option<&Race>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    race.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Address of the interface used for reaching other machines, tried through the internet and
+/// then common local networks. Connecting a UDP socket only picks the route.
+fn local_ip() -> Option<IpAddr> {
+    ROUTE_PROBES.iter().find_map(|probe| {
+        let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+        socket.connect(probe).ok()?;
+        let ip = socket.local_addr().ok()?.ip();
+        (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point;
+    use std::io::Read;
+
+    fn race() -> Race {
+        Race::new(
+            &[Point::new(10.0, 20.0), Point::new(30.0, 40.0)],
+            &[],
+            &["Sports".to_string()],
+            7,
+        )
+    }
+
+    #[test]
+    fn guest_follows_host() {
+        let mut host = Host::start(0).unwrap();
+        assert_ne!(host.port, 0);
+        let address = format!("127.0.0.1:{}", host.port);
+
+        // Nothing published yet
+        let response = ureq::get(&format!("http://{}/race.json", address)).call();
+        assert!(matches!(response, Err(ureq::Error::Status(404, _))));
+
+        let race = race();
+        assert!(settled(&mut host, Some(&race)));
+        host.publish(Some(&race), "Road map", Some(image::RgbaImage::new(4, 3)));
+        assert!(!host.needs_update(Some(&race)));
+
+        // Encoded on the server thread when asked for
+        let mut png = Vec::new();
+        ureq::get(&format!("http://{}/race.png", address))
+            .call()
+            .unwrap()
+            .into_reader()
+            .read_to_end(&mut png)
+            .unwrap();
+        let served = image::load_from_memory(&png).unwrap();
+        assert_eq!((served.width(), served.height()), (4, 3));
+
+        let guest = Guest::join(&address);
+        let update = (0..50)
+            .find_map(|_| {
+                thread::sleep(Duration::from_millis(100));
+                guest.poll()
+            })
+            .expect("no update from the host")
+            .unwrap();
+        assert_eq!(update.map, "Road map");
+        assert_eq!(update.race.seed, 7);
+        assert!(update.race.checkpoints == race.checkpoints);
+    }
+
+    /// Whether the host wants to publish `race` once it has stayed the same long enough
+    fn settled(host: &mut Host, race: Option<&Race>) -> bool {
+        host.needs_update(race);
+        thread::sleep(PUBLISH_DELAY);
+        host.needs_update(race)
+    }
+
+    #[test]
+    fn changed_race_needs_update() {
+        let mut host = Host::start(0).unwrap();
+        let mut race = race();
+        host.publish(Some(&race), "Road map", None);
+
+        race.checkpoints[1].0.x += 1.0;
+        // Not while it's still being edited
+        assert!(!host.needs_update(Some(&race)));
+        race.checkpoints[1].0.x += 1.0;
+        assert!(!host.needs_update(Some(&race)));
+        assert!(settled(&mut host, Some(&race)));
+        assert!(settled(&mut host, None));
+    }
+}
@@ -1,17 +1,62 @@
 use std::{
     borrow::Cow,
-    env, fs,
+    hash::{Hash, Hasher},
     ops::{Div, Mul},
+    path::PathBuf,
+    sync::mpsc::{Receiver, TryRecvError},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
+use chrono::{DateTime, Local};
+use cli::Args;
+use config::Config;
+use difficulty::Difficulty;
 use egui_macroquad::egui;
-use macroquad::{prelude::*, rand::ChooseRandom};
+use export::ExportConfig;
+use history::History;
+use macroquad::{prelude::*, rand::ChooseRandom, window::Conf};
+use maps::MapConfig;
+use ordering::RouteOrder;
+use results::Leaderboard;
+use roads::RoadGraph;
 use serde::{Deserialize, Serialize};
-
-const CIRCLE_RADIUS: f32 = 30.0;
-const CIRCLE_THICKNESS: f32 = 5.0;
-const LINE_THICKNESS: f32 = 7.0;
-const TEXT_SIZE: f32 = 100.0;
+use series::Series;
+use session::Session;
+use style::{color, Fonts, Style};
+use terrain::{Crossing, Terrain, TerrainMask, WaterLegs};
+
+mod cli;
+mod config;
+mod difficulty;
+mod export;
+mod history;
+mod list_edit;
+mod lobby;
+mod maps;
+mod ordering;
+mod paths;
+mod results;
+mod roads;
+mod roster;
+mod series;
+mod session;
+mod share;
+mod stopwatch;
+mod style;
+mod terrain;
+mod text_export;
+mod webhook;
+
+/// Races generated at most to find one that fits the terrain settings
+const TERRAIN_ATTEMPTS: usize = 20;
+const TERRAIN_MISFIT: &str =
+    "No race avoiding water or restricted areas was found, some legs still cross them";
+/// Distance between direction arrows on the route in map pixels
+const ARROW_SPACING: f32 = 250.0;
+/// Distance from a checkpoint to the start of its labels in map pixels
+const LABEL_OFFSET: f32 = 20.0;
+/// Seconds between session autosaves
+const AUTOSAVE_INTERVAL: f64 = 5.0;
 
 const DEFAULT_POINTS: &[Point] = &[
     Point { x: 580., y: 352. },
@@ -75,6 +120,14 @@ impl Div<f32> for Point {
     }
 }
 
+// By bits, so a moved point always hashes differently
+impl Hash for Point {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.x.to_bits().hash(state);
+        self.y.to_bits().hash(state);
+    }
+}
+
 impl Point {
     #[inline(always)]
     fn new(x: f32, y: f32) -> Self {
@@ -94,9 +147,48 @@ impl From<Point> for Vec2 {
     }
 }
 
+/// Where and at which scale the map texture is drawn on the screen
+#[derive(Clone, Copy)]
+struct MapView {
+    offset: Vec2,
+    scale_factor: f32,
+}
+
+impl MapView {
+    /// Fit the map into `area` keeping its aspect ratio and center it in both dimensions
+    fn fit(map_size: Vec2, area: Rect) -> Self {
+        let scale_factor = (area.w / map_size.x).min(area.h / map_size.y).max(0.0);
+        let size = map_size * scale_factor;
+
+        Self {
+            offset: Vec2::new(
+                area.x + (area.w - size.x) / 2.0,
+                area.y + (area.h - size.y) / 2.0,
+            ),
+            scale_factor,
+        }
+    }
+
+    fn to_screen(self, point: Point) -> Point {
+        let scaled = point.mul(self.scale_factor);
+        Point::new(scaled.x + self.offset.x, scaled.y + self.offset.y)
+    }
+
+    fn to_map(self, pos: (f32, f32)) -> Point {
+        Point::new(pos.0 - self.offset.x, pos.1 - self.offset.y).div(self.scale_factor)
+    }
+
+    fn contains(self, map_size: Vec2, pos: (f32, f32)) -> bool {
+        let point = self.to_map(pos);
+        point.x >= 0.0 && point.y >= 0.0 && point.x < map_size.x && point.y < map_size.y
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 enum State {
     Race(RaceState),
-    Config(Vec<Point>),
+    Config(Vec<(Point, String)>),
+    Style(Style),
     Idle,
 }
 
@@ -112,12 +204,47 @@ impl State {
     }
 }
 
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 struct RaceState {
     length: usize,
     n_tryhisuojaus: usize,
     classes: Vec<String>,
     edit_controls_collapsed: bool,
+    /// Number of races generated for a series
+    #[serde(default = "default_series_length")]
+    series_length: usize,
+    /// Difficulty generated races aim for
+    #[serde(default)]
+    difficulty: Difficulty,
+    /// Order the picked checkpoints are driven in
+    #[serde(default)]
+    order: RouteOrder,
+}
+
+fn default_series_length() -> usize {
+    4
+}
+
+enum Status {
+    Info(String),
+    Error(String),
+}
+
+enum ExportAction {
+    CopyImage,
+    CopyText,
+    CopyShareCode,
+    SaveImage,
+    Post,
+}
+
+enum SeriesAction {
+    Generate,
+    Select(usize),
+    ExportImages,
+    Save,
+    Open(String),
+    Close,
 }
 
 enum TweakAction {
@@ -137,112 +264,386 @@ impl Default for RaceState {
             n_tryhisuojaus: 0,
             classes: vec!["".to_string(); 10],
             edit_controls_collapsed: false,
+            series_length: default_series_length(),
+            difficulty: Difficulty::Any,
+            order: RouteOrder::Random,
         }
     }
 }
 
-#[derive(Deserialize, Serialize)]
-struct Config {
-    race_points: Vec<Point>,
-}
+impl RaceState {
+    /// Settings matching an existing race, so rerolling keeps its shape
+    fn from_race(race: &Race) -> Self {
+        let mut classes = race
+            .checkpoints
+            .iter()
+            .filter_map(|(_, class)| class.clone())
+            .collect::<Vec<_>>();
+        classes.resize(classes.len().max(10), String::new());
 
-impl Default for Config {
-    fn default() -> Self {
         Self {
-            race_points: DEFAULT_POINTS.to_vec(),
+            length: race.checkpoints.len().min(10),
+            n_tryhisuojaus: race.tryhisuojaus.len().min(4),
+            classes,
+            edit_controls_collapsed: false,
+            series_length: default_series_length(),
+            difficulty: Difficulty::Any,
+            order: RouteOrder::Random,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Hash)]
 struct Race {
     // Indices of existing checkpoints
     checkpoints: Vec<(Point, Option<String>)>,
     tryhisuojaus: Vec<(usize, Point)>,
+    /// Seed the race was generated with
+    seed: u64,
+    generated_at: DateTime<Local>,
+    /// Finishes entered after the race was driven
+    #[serde(default)]
+    results: Vec<results::Finish>,
+    /// Splits timed while the race was driven
+    #[serde(default)]
+    stopwatch: stopwatch::Stopwatch,
+    /// Class or vehicle each player of the roster drives on each leg
+    #[serde(default)]
+    assignments: Vec<(String, Vec<String>)>,
+}
+
+impl Race {
+    /// Race through `points` with the tryhisuojaus placed on random legs
+    fn new(points: &[Point], tryhisuojaus: &[Point], classes: &[String], seed: u64) -> Self {
+        let mut tryhisuojaus = tryhisuojaus
+            .iter()
+            .map(|point| (rand::gen_range(0, points.len() - 2), *point))
+            .collect::<Vec<_>>();
+        tryhisuojaus.sort_by_key(|(i, _)| *i);
+
+        Self {
+            checkpoints: points
+                .iter()
+                .copied()
+                .enumerate()
+                .map(|(i, point)| match classes.get(i) {
+                    Some(class) => (point, Some(class.clone())),
+                    None => (point, None),
+                })
+                .collect(),
+            tryhisuojaus,
+            seed,
+            generated_at: Local::now(),
+            results: Vec::new(),
+            stopwatch: Default::default(),
+            assignments: Vec::new(),
+        }
+    }
+
+    /// Class of every leg, empty where none is set
+    fn classes(&self) -> Vec<String> {
+        self.checkpoints[..self.legs()]
+            .iter()
+            .map(|(_, class)| class.clone().unwrap_or_default())
+            .collect()
+    }
+
+    /// Number of legs between checkpoints
+    fn legs(&self) -> usize {
+        self.checkpoints.len().saturating_sub(1)
+    }
+
+    /// Points driven through on a leg, including its tryhisuojaus detours
+    fn leg_path(&self, leg: usize) -> Vec<Point> {
+        let mut path = vec![self.checkpoints[leg].0];
+        path.extend(
+            self.tryhisuojaus
+                .iter()
+                .filter(|(index, _)| *index == leg)
+                .map(|(_, point)| *point),
+        );
+        path.push(self.checkpoints[leg + 1].0);
+        path
+    }
+
+    /// Length of the whole race in map pixels
+    fn length(&self, roads: Option<&RoadGraph>) -> f32 {
+        (0..self.legs())
+            .map(|leg| self.leg_length(leg, roads))
+            .sum()
+    }
+
+    /// Length of a leg in map pixels, including its tryhisuojaus detours
+    fn leg_length(&self, leg: usize, roads: Option<&RoadGraph>) -> f32 {
+        self.leg_path(leg)
+            .windows(2)
+            .map(|pair| leg_distance(pair[0], pair[1], roads))
+            .sum()
+    }
+}
+
+/// Driving distance along the roads if a road graph is loaded, straight otherwise
+fn leg_distance(from: Point, to: Point, roads: Option<&RoadGraph>) -> f32 {
+    match roads {
+        Some(roads) => roads.distance(from, to),
+        None => from.distance_to((to.x, to.y)),
+    }
+}
+
+/// Draw connected line segments through `points`
+fn draw_path(points: &[Point], thickness: f32, color: Color) {
+    for pair in points.windows(2) {
+        draw_line(pair[0].x, pair[0].y, pair[1].x, pair[1].y, thickness, color);
+    }
+}
+
+/// Draw arrowheads evenly spaced along a path, pointing the way it goes
+fn draw_arrows(path: &[Point], size: f32, spacing: f32, color: Color) {
+    let segments = path
+        .windows(2)
+        .map(|pair| (Vec2::from(pair[0]), Vec2::from(pair[1])))
+        .filter(|(from, to)| from.distance(*to) > 0.0)
+        .collect::<Vec<_>>();
+    let length = segments
+        .iter()
+        .map(|(from, to)| from.distance(*to))
+        .sum::<f32>();
+
+    if length < size * 2.0 {
+        return;
+    }
+
+    let count = (length / spacing).floor().max(1.0) as usize;
+
+    for i in 0..count {
+        // Distance of the arrow's middle from the start of the path
+        let mut along = length * (i as f32 + 0.5) / count as f32;
+        for (from, to) in &segments {
+            let segment = from.distance(*to);
+            if along <= segment {
+                let direction = (*to - *from) / segment;
+                let normal = direction.perp() * size / 2.0;
+                let tip = *from + direction * (along + size / 2.0);
+                let base = tip - direction * size;
+                draw_triangle(tip, base + normal, base - normal, color);
+                break;
+            }
+            along -= segment;
+        }
+    }
+}
+
+/// What a leg passes through on its driving path, along the roads if there are any
+fn leg_crossing(
+    race: &Race,
+    leg: usize,
+    terrain: &TerrainMask,
+    roads: Option<&RoadGraph>,
+    map_size: Vec2,
+) -> Crossing {
+    let path = race
+        .leg_path(leg)
+        .windows(2)
+        .flat_map(|pair| match roads {
+            Some(roads) => roads.path(pair[0], pair[1]),
+            None => pair.to_vec(),
+        })
+        .collect::<Vec<_>>();
+
+    terrain.crossing(&path, map_size)
+}
+
+fn format_distance(meters: f32) -> String {
+    format!("{:.1} km", meters / 1000.0)
 }
 
 struct RuntimeData {
     state: State,
     config: Config,
-    font: Font,
-    font_outline: Font,
+    fonts: Fonts,
     gtav_map: Texture2D,
+    /// Road graph of the selected map, legs are measured in straight lines without one
+    roads: Option<RoadGraph>,
+    /// Water and restricted areas of the selected map
+    terrain: Option<TerrainMask>,
     race: Option<Race>,
-    config_path: String,
-    clipboard: arboard::Clipboard,
+    /// Series being stepped through, the current race is kept in `race` while shown
+    series: Option<Series>,
+    config_path: PathBuf,
+    /// Profile the config belongs to, the default one if not set
+    profile: Option<String>,
+    /// Config file given on the command line, profiles can't be switched then
+    fixed_config_path: bool,
+    /// Problem with loading the config, shown in a dialog until dismissed
+    config_error: Option<String>,
+    /// Config as last loaded or written, to skip saves when nothing changed
+    saved_config: String,
+    session_path: PathBuf,
+    /// Last written session, to skip autosaves when nothing changed
+    saved_session: String,
+    /// Where user supplied map images are looked up
+    data_dirs: Vec<PathBuf>,
+    /// Missing on systems without a usable clipboard
+    clipboard: Option<arboard::Clipboard>,
+    /// Result of the last action that can fail, shown at the top of the panel
+    status: Option<Status>,
+    /// Share code being typed in on the idle screen
+    share_code: String,
+    /// Server publishing the race to guests
+    host: Option<lobby::Host>,
+    /// Host whose race is being followed
+    guest: Option<lobby::Guest>,
+    /// Webhook request in flight
+    webhook_post: Option<Receiver<Result<(), String>>>,
+    leaderboard: Leaderboard,
+    /// Points of earlier races, to keep them from coming up again too soon
+    history: History,
 }
 
 impl RuntimeData {
     fn run(&mut self) {
+        self.update_lobby();
+        self.update_webhook();
+
         clear_background(BLACK);
 
-        let scale_factor = screen_height() / self.gtav_map.height();
+        let mut map_area = Rect::new(0.0, 0.0, screen_width(), screen_height());
+        let mut pointer_over_ui = false;
+
+        egui_macroquad::ui(|ctx| {
+            if let Some(error) = &self.config_error {
+                let mut dismissed = false;
+                egui::Window::new("Config error")
+                    .collapsible(false)
+                    .resizable(false)
+                    .anchor(egui::Align2::CENTER_CENTER, (0.0, 0.0))
+                    .show(ctx, |ui| {
+                        ui.label(error);
+                        dismissed = ui.button("OK").clicked();
+                    });
+                if dismissed {
+                    self.config_error = None;
+                }
+            }
+
+            egui::SidePanel::right("controls")
+                .resizable(true)
+                .show(ctx, |ui| {
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        self.main_ui(ui);
+                    });
+                });
+
+            // egui works in points, macroquad in physical pixels
+            let pixels_per_point = ctx.pixels_per_point();
+            let available = ctx.available_rect();
+            map_area = Rect::new(
+                available.min.x * pixels_per_point,
+                available.min.y * pixels_per_point,
+                available.width() * pixels_per_point,
+                available.height() * pixels_per_point,
+            );
+            pointer_over_ui = ctx.wants_pointer_input() || ctx.is_pointer_over_area();
+        });
+
+        let map_size = self.map_size();
+        let map_view = MapView::fit(map_size, map_area);
 
         draw_texture_ex(
             self.gtav_map,
-            0.0,
-            0.0,
+            map_view.offset.x,
+            map_view.offset.y,
             WHITE,
             DrawTextureParams {
-                dest_size: Some(Vec2 {
-                    x: self.gtav_map.width() * scale_factor,
-                    y: self.gtav_map.height() * scale_factor,
-                }),
+                dest_size: Some(map_size * map_view.scale_factor),
                 ..Default::default()
             },
         );
 
-        let mut window_size =
-            egui::Rect::from_min_max(egui::Pos2::new(0.0, 0.0), egui::Pos2::new(0.0, 0.0));
-
-        egui_macroquad::ui(|ctx| {
-            egui::Window::new("GTAV Race Gen 2: Electric Boogaloo")
-                .title_bar(false)
-                .anchor(egui::Align2::RIGHT_TOP, (0.0, 0.0))
-                .resizable(false)
-                .show(ctx, |ui| {
-                    window_size = self.main_ui(ui);
-                });
-        });
-
         match &mut self.state {
             State::Idle => {}
             State::Config(race_points) => {
                 let mouse_pos = mouse_position();
+                let mouse_on_map = !pointer_over_ui && map_view.contains(map_size, mouse_pos);
 
                 let mut delete = None;
 
-                for (i, point) in race_points.iter().enumerate() {
-                    let scaled = point.mul(scale_factor);
+                for (i, (point, _)) in race_points.iter().enumerate() {
+                    let scaled = map_view.to_screen(*point);
                     let distance = scaled.distance_to(mouse_pos);
 
-                    if is_mouse_button_pressed(MouseButton::Right) && distance < 10.0 {
+                    if mouse_on_map
+                        && is_mouse_button_pressed(MouseButton::Right)
+                        && distance < 10.0
+                    {
                         delete = Some(i);
                     }
 
+                    let freshness = &self.config.freshness;
+                    let age = self
+                        .history
+                        .age(&self.config.map().name, *point, freshness.window);
+
                     draw_circle_lines(
                         scaled.x,
                         scaled.y,
                         10.0,
                         3.0,
-                        if distance < 10.0 { WHITE } else { RED },
+                        match age {
+                            _ if mouse_on_map && distance < 10.0 => WHITE,
+                            None => RED,
+                            // Fades from blue for the latest race to red for unused points
+                            Some(age) => Color::from_vec(BLUE.to_vec().lerp(
+                                RED.to_vec(),
+                                (age + 1) as f32 / (freshness.window + 1) as f32,
+                            )),
+                        },
                     );
+
+                    // Ring points that can't be driven to
+                    let terrain = self
+                        .terrain
+                        .as_ref()
+                        .map(|terrain| terrain.at(*point, map_size));
+                    if let Some(ring) = match terrain {
+                        Some(Terrain::Water) => Some(SKYBLUE),
+                        Some(Terrain::Restricted) => Some(ORANGE),
+                        _ => None,
+                    } {
+                        draw_circle_lines(scaled.x, scaled.y, 15.0, 3.0, ring);
+                    }
                 }
 
                 if let Some(i) = delete {
                     race_points.remove(i);
                 }
 
-                if is_mouse_button_pressed(MouseButton::Left)
-                    && !(mouse_pos.0 > screen_width() - window_size.width()
-                        && mouse_pos.1 < window_size.height())
-                    && mouse_pos.0 < self.gtav_map.width() * scale_factor
-                {
-                    race_points.push(Point::new(mouse_pos.0, mouse_pos.1).div(scale_factor))
+                if mouse_on_map && is_mouse_button_pressed(MouseButton::Left) {
+                    race_points.push((map_view.to_map(mouse_pos), String::new()))
+                }
+            }
+            State::Style(style) => {
+                let style = style.clone();
+                match &self.race {
+                    Some(race) => self.draw_race(race, map_view, &style),
+                    None => {
+                        if let Some(race) = self.preview_race() {
+                            self.draw_race(&race, map_view, &style);
+                        }
+                    }
                 }
             }
             State::Race(_) => {
                 if let Some(race) = &self.race {
-                    self.draw_race(race, scale_factor);
+                    self.draw_race(race, map_view, &self.config.style);
+                }
+
+                let mouse_pos = mouse_position();
+                if !pointer_over_ui
+                    && map_view.contains(map_size, mouse_pos)
+                    && is_mouse_button_pressed(MouseButton::Left)
+                {
+                    self.click_checkpoint(map_view.to_map(mouse_pos));
                 }
             }
         }
@@ -250,67 +651,80 @@ impl RuntimeData {
         egui_macroquad::draw();
     }
 
-    fn draw_race(&self, race: &Race, scale_factor: f32) {
+    fn map_size(&self) -> Vec2 {
+        Vec2::new(self.gtav_map.width(), self.gtav_map.height())
+    }
+
+    fn draw_race(&self, race: &Race, map_view: MapView, style: &Style) {
+        let scale_factor = map_view.scale_factor;
+
+        let screen_path = |from: Point, to: Point| {
+            self.route_path(from, to, style)
+                .into_iter()
+                .map(|point| map_view.to_screen(point))
+                .collect::<Vec<_>>()
+        };
+
         let mut last_point: Option<Point> = None;
 
         for (i, (point, class)) in race.checkpoints.iter().enumerate() {
-            let scaled = point.mul(scale_factor);
+            let scaled = map_view.to_screen(*point);
 
             if let Some(last_point) = last_point {
-                draw_line(
-                    scaled.x,
-                    scaled.y,
-                    last_point.x,
-                    last_point.y,
-                    LINE_THICKNESS * scale_factor,
-                    Color::from_rgba(255, 0, 0, 100),
+                draw_path(
+                    &screen_path(last_point, *point),
+                    style.line_thickness * scale_factor,
+                    color(style.route_color),
                 );
             }
 
             draw_circle_lines(
                 scaled.x,
                 scaled.y,
-                CIRCLE_RADIUS * scale_factor,
-                CIRCLE_THICKNESS * scale_factor,
-                RED,
+                style.circle_radius * scale_factor,
+                style.circle_thickness * scale_factor,
+                color(style.checkpoint_color),
             );
 
             self.draw_bold_text(
                 &format!("{}", i + 1),
-                scaled.x + 20.0 * scale_factor,
+                scaled.x + LABEL_OFFSET * scale_factor,
                 scaled.y,
-                (TEXT_SIZE * scale_factor) as u16,
-                WHITE,
-                BLACK,
+                (style.text_size * scale_factor) as u16,
+                color(style.text_color),
+                color(style.text_outline_color),
+                style,
             );
             match class {
                 Some(class) => {
                     self.draw_bold_text(
                         class,
-                        scaled.x + 20.0 * scale_factor,
-                        scaled.y + 70.0 * scale_factor,
-                        (TEXT_SIZE * scale_factor) as u16,
-                        WHITE,
-                        BLACK,
+                        scaled.x + LABEL_OFFSET * scale_factor,
+                        scaled.y + style.text_size * 0.7 * scale_factor,
+                        (style.text_size * scale_factor) as u16,
+                        color(style.text_color),
+                        color(style.text_outline_color),
+                        style,
                     );
                 }
                 None => {
                     self.draw_bold_text(
                         "Goal",
-                        scaled.x + 20.0 * scale_factor,
-                        scaled.y + 70.0 * scale_factor,
-                        (TEXT_SIZE * scale_factor) as u16,
-                        WHITE,
-                        BLACK,
+                        scaled.x + LABEL_OFFSET * scale_factor,
+                        scaled.y + style.text_size * 0.7 * scale_factor,
+                        (style.text_size * scale_factor) as u16,
+                        color(style.text_color),
+                        color(style.text_outline_color),
+                        style,
                     );
                 }
             }
 
-            last_point = Some(scaled);
+            last_point = Some(*point);
         }
 
         for (i, (index, point)) in race.tryhisuojaus.iter().enumerate() {
-            let scaled = point.mul(scale_factor);
+            let scaled = map_view.to_screen(*point);
 
             let last_index = if i == 0 {
                 None
@@ -323,67 +737,154 @@ impl RuntimeData {
                 if last_index == index {
                     None
                 } else {
-                    Some(race.checkpoints[*index].0.mul(scale_factor))
+                    Some(race.checkpoints[*index].0)
                 }
             } else {
-                Some(race.checkpoints[*index].0.mul(scale_factor))
+                Some(race.checkpoints[*index].0)
             };
 
             let end = if let Some((next_index, point)) = next_index {
                 if next_index == index {
-                    point.mul(scale_factor)
+                    *point
                 } else {
-                    race.checkpoints[*index + 1].0.mul(scale_factor)
+                    race.checkpoints[*index + 1].0
                 }
             } else {
-                race.checkpoints[*index + 1].0.mul(scale_factor)
+                race.checkpoints[*index + 1].0
             };
 
             draw_circle_lines(
                 scaled.x,
                 scaled.y,
-                CIRCLE_RADIUS * scale_factor,
-                CIRCLE_THICKNESS * scale_factor,
-                Color::from_rgba(0, 0, 255, 255),
+                style.circle_radius * scale_factor,
+                style.circle_thickness * scale_factor,
+                color(style.tryhisuojaus_color),
             );
 
             if let Some(start) = start {
-                draw_line(
-                    scaled.x,
-                    scaled.y,
-                    start.x,
-                    start.y,
-                    LINE_THICKNESS * scale_factor,
-                    Color::from_rgba(0, 0, 255, 100),
+                draw_path(
+                    &screen_path(start, *point),
+                    style.line_thickness * scale_factor,
+                    color(style.tryhisuojaus_route_color),
                 );
             }
-            draw_line(
-                scaled.x,
-                scaled.y,
-                end.x,
-                end.y,
-                LINE_THICKNESS * scale_factor,
-                Color::from_rgba(0, 0, 255, 100),
+            draw_path(
+                &screen_path(*point, end),
+                style.line_thickness * scale_factor,
+                color(style.tryhisuojaus_route_color),
             );
 
             self.draw_bold_text(
                 &format!("{}", i + 1),
-                scaled.x + 20.0,
-                scaled.y + TEXT_SIZE * scale_factor / 2.0,
-                (TEXT_SIZE * scale_factor) as u16,
-                Color::from_rgba(0, 0, 255, 255),
-                WHITE,
+                scaled.x + LABEL_OFFSET * scale_factor,
+                scaled.y + style.text_size * scale_factor / 2.0,
+                (style.text_size * scale_factor) as u16,
+                color(style.tryhisuojaus_text_color),
+                color(style.tryhisuojaus_text_outline_color),
+                style,
             );
         }
+
+        if style.arrows {
+            let size = style.line_thickness * 3.0 * scale_factor;
+            let spacing = ARROW_SPACING * scale_factor;
+
+            for leg in 0..race.legs() {
+                draw_arrows(
+                    &screen_path(race.checkpoints[leg].0, race.checkpoints[leg + 1].0),
+                    size,
+                    spacing,
+                    color(style.checkpoint_color),
+                );
+
+                let path = race.leg_path(leg);
+                if path.len() > 2 {
+                    for pair in path.windows(2) {
+                        draw_arrows(
+                            &screen_path(pair[0], pair[1]),
+                            size,
+                            spacing,
+                            color(style.tryhisuojaus_color),
+                        );
+                    }
+                }
+            }
+        }
+
+        if style.leg_labels {
+            let font_size = (style.text_size * 0.4 * scale_factor) as u16;
+
+            for leg in 0..race.legs() {
+                let from = map_view.to_screen(race.checkpoints[leg].0);
+                let to = map_view.to_screen(race.checkpoints[leg + 1].0);
+
+                let mut label = format!(
+                    "Leg {}: {}",
+                    leg + 1,
+                    format_distance(
+                        race.leg_length(leg, self.roads.as_ref())
+                            * self.config.map().meters_per_pixel
+                    )
+                );
+                if let Some(class) = race.checkpoints[leg].1.as_ref().filter(|c| !c.is_empty()) {
+                    label.push_str(&format!(", {}", class));
+                }
+
+                let dimensions = measure_text(
+                    &label,
+                    Some(self.fonts.get(style.font).fill),
+                    font_size,
+                    1.0,
+                );
+                self.draw_bold_text(
+                    &label,
+                    (from.x + to.x) / 2.0 - dimensions.width / 2.0,
+                    (from.y + to.y) / 2.0 + dimensions.height / 2.0,
+                    font_size,
+                    color(style.text_color),
+                    color(style.text_outline_color),
+                    style,
+                );
+            }
+        }
     }
 
-    fn main_ui(&mut self, ui: &mut egui::Ui) -> egui::Rect {
+    /// Points a leg is drawn through in map pixels, along the roads if enabled
+    fn route_path(&self, from: Point, to: Point, style: &Style) -> Vec<Point> {
+        match &self.roads {
+            Some(roads) if style.road_paths => roads.path(from, to),
+            _ => vec![from, to],
+        }
+    }
+
+    fn main_ui(&mut self, ui: &mut egui::Ui) {
         ui.heading("GTAV Race Gen 2: Electric Boogaloo");
         ui.separator();
 
+        if let Some(status) = &self.status {
+            let mut dismiss = false;
+            ui.horizontal_wrapped(|ui| {
+                match status {
+                    Status::Info(message) => ui.label(message),
+                    Status::Error(message) => ui.colored_label(egui::Color32::RED, message),
+                };
+                dismiss = ui.small_button("x").clicked();
+            });
+            if dismiss {
+                self.status = None;
+            }
+            ui.separator();
+        }
+
+        if self.host.is_some() || self.guest.is_some() {
+            self.lobby_status_ui(ui);
+            ui.separator();
+        }
+
         match &mut self.state {
             State::Race(race_state) => {
                 let mut tweak_action = None;
+                let mut classes_edited = false;
 
                 if !race_state.edit_controls_collapsed {
                     ui.add(
@@ -396,6 +897,25 @@ impl RuntimeData {
                             .show_value(true)
                             .text("Tryhisuojaus checkpoints"),
                     );
+                    self.config.freshness.ui(ui);
+                    egui::ComboBox::from_label("Route order")
+                        .selected_text(race_state.order.name())
+                        .show_ui(ui, |ui| {
+                            for order in RouteOrder::ALL {
+                                ui.selectable_value(&mut race_state.order, *order, order.name());
+                            }
+                        });
+                    egui::ComboBox::from_label("Difficulty")
+                        .selected_text(race_state.difficulty.name())
+                        .show_ui(ui, |ui| {
+                            for difficulty in Difficulty::ALL {
+                                ui.selectable_value(
+                                    &mut race_state.difficulty,
+                                    *difficulty,
+                                    difficulty.name(),
+                                );
+                            }
+                        });
 
                     ui.separator();
 
@@ -415,7 +935,7 @@ impl RuntimeData {
                                 ui.label(format!("{}: ", i + 1));
                                 match class {
                                     Some(class) => {
-                                        ui.text_edit_singleline(class);
+                                        classes_edited |= ui.text_edit_singleline(class).changed();
                                     }
                                     None => {
                                         ui.label("Goal");
@@ -464,30 +984,107 @@ impl RuntimeData {
                     }
                 }
 
+                let mut export_action = None;
+
+                if let Some(race) = &self.race {
+                    let score = difficulty::score(
+                        race,
+                        self.config.map().meters_per_pixel,
+                        self.roads.as_ref(),
+                        &self.config.difficulty,
+                    );
+                    ui.label(format!(
+                        "Difficulty {:.0} ({})",
+                        score.total(),
+                        Difficulty::of(score.total()).name()
+                    ))
+                    .on_hover_text(score.summary());
+
+                    let map_size = Vec2::new(self.gtav_map.width(), self.gtav_map.height());
+                    let crossings = self.terrain.iter().flat_map(|terrain| {
+                        (0..race.legs()).map(|leg| {
+                            leg_crossing(race, leg, terrain, self.roads.as_ref(), map_size)
+                        })
+                    });
+                    for (leg, crossing) in crossings.enumerate() {
+                        if self.config.terrain.crosses_water(&crossing) {
+                            ui.colored_label(
+                                egui::Color32::LIGHT_BLUE,
+                                format!("Leg {} crosses water", leg + 1),
+                            );
+                        }
+                        if crossing.restricted {
+                            ui.colored_label(
+                                egui::Color32::from_rgb(255, 165, 0),
+                                format!("Leg {} passes a restricted area", leg + 1),
+                            );
+                        }
+                    }
+                }
+
                 if race_state.length > 1 {
                     let race_state = race_state.clone();
                     ui.horizontal(|ui| {
-                        if ui.button("Generate race").clicked() {
-                            self.race = Some(self.generate_race(race_state))
+                        let pool_size = self.config.map().race_points.len();
+                        if ui
+                            .add_enabled(
+                                pool_size >= race_state.length + race_state.n_tryhisuojaus,
+                                egui::Button::new("Generate race"),
+                            )
+                            .on_disabled_hover_text("Not enough checkpoints in the pool")
+                            .clicked()
+                        {
+                            let (race, fits) = self.generate_race(race_state);
+                            self.record_history(std::slice::from_ref(&race));
+                            self.race = Some(race);
+                            if !fits {
+                                self.status = Some(Status::Info(TERRAIN_MISFIT.to_string()));
+                            }
                         }
-                        if self.race.is_some() && ui.button("Copy map to clipboard").clicked() {
-                            let image = self.race_image(self.race.as_ref().unwrap());
-
-                            self.clipboard
-                                .set_image(arboard::ImageData {
-                                    width: image.width(),
-                                    height: image.height(),
-                                    bytes: Cow::from_iter(
-                                        image.get_image_data().iter().flatten().cloned(),
-                                    ),
-                                })
-                                .unwrap();
+                        if self.race.is_some() {
+                            if ui.button("Copy map to clipboard").clicked() {
+                                export_action = Some(ExportAction::CopyImage);
+                            }
+                            if ui.button("Copy as text").clicked() {
+                                export_action = Some(ExportAction::CopyText);
+                            }
+                            if ui.button("Copy share code").clicked() {
+                                export_action = Some(ExportAction::CopyShareCode);
+                            }
+                            if ui.button("Save image").clicked() {
+                                export_action = Some(ExportAction::SaveImage);
+                            }
+                            if ui
+                                .add_enabled(
+                                    !self.config.webhook.url.trim().is_empty()
+                                        && self.webhook_post.is_none(),
+                                    egui::Button::new("Post"),
+                                )
+                                .on_disabled_hover_text("Set a webhook URL under \"Webhook\" first")
+                                .clicked()
+                            {
+                                export_action = Some(ExportAction::Post);
+                            }
                         }
                         if ui.button("Toggle edit controls").clicked() {
                             self.state.toggle_edit_controls();
                         }
                     });
                 }
+                match export_action {
+                    Some(ExportAction::CopyImage) => self.copy_image(),
+                    Some(ExportAction::CopyText) => self.copy_text(),
+                    Some(ExportAction::CopyShareCode) => self.copy_share_code(),
+                    Some(ExportAction::SaveImage) => self.save_image(),
+                    Some(ExportAction::Post) => self.post_race(),
+                    None => (),
+                }
+                // Adding or deleting a checkpoint changes the legs the roster was assigned to
+                let legs_changed = classes_edited
+                    || matches!(
+                        tweak_action,
+                        Some(TweakAction::Delete(_)) | Some(TweakAction::Add(_))
+                    );
                 match tweak_action {
                     Some(TweakAction::Delete(i)) => {
                         self.race.as_mut().unwrap().checkpoints.remove(i);
@@ -500,77 +1097,128 @@ impl RuntimeData {
                             .1 = None;
                     }
                     Some(TweakAction::Reroll(i)) => {
-                        self.race
-                            .as_mut()
-                            .unwrap()
-                            .checkpoints
-                            .get_mut(i)
-                            .unwrap()
-                            .0 = self.random_point(self.race.as_ref().unwrap());
+                        if let Some(point) = self.unused_point() {
+                            self.race.as_mut().unwrap().checkpoints[i].0 = point;
+                        }
                     }
                     Some(TweakAction::Add(i)) => {
-                        let point = self.random_point(self.race.as_ref().unwrap());
-                        self.race
-                            .as_mut()
-                            .unwrap()
-                            .checkpoints
-                            .insert(i + 1, (point, Some(String::new())));
-
-                        self.race
-                            .as_mut()
-                            .unwrap()
-                            .checkpoints
-                            .last_mut()
-                            .unwrap()
-                            .1 = None;
+                        if let Some(point) = self.unused_point() {
+                            let race = self.race.as_mut().unwrap();
+                            race.checkpoints.insert(i + 1, (point, Some(String::new())));
+                            race.checkpoints.last_mut().unwrap().1 = None;
+                        }
                     }
                     Some(TweakAction::DeleteTs(i)) => {
                         self.race.as_mut().unwrap().tryhisuojaus.remove(i);
                     }
                     Some(TweakAction::RerollTsPoint(i)) => {
-                        self.race
-                            .as_mut()
-                            .unwrap()
-                            .tryhisuojaus
-                            .get_mut(i)
-                            .unwrap()
-                            .1 = self.random_point(self.race.as_ref().unwrap());
+                        if let Some(point) = self.unused_point() {
+                            self.race.as_mut().unwrap().tryhisuojaus[i].1 = point;
+                        }
                     }
                     Some(TweakAction::RerollTs(i)) => {
-                        let len = self.race.as_ref().unwrap().checkpoints.len();
-                        *self.race.as_mut().unwrap().tryhisuojaus.get_mut(i).unwrap() = (
-                            rand::gen_range(0, len - 1),
-                            self.random_point(self.race.as_ref().unwrap()),
-                        );
+                        if let Some(point) = self.unused_point() {
+                            let race = self.race.as_mut().unwrap();
+                            let len = race.checkpoints.len();
+                            race.tryhisuojaus[i] = (rand::gen_range(0, len - 1), point);
+                        }
                     }
                     Some(TweakAction::AddTs(index)) => {
-                        let point = self.random_point(self.race.as_ref().unwrap());
-                        self.race
-                            .as_mut()
-                            .unwrap()
-                            .tryhisuojaus
-                            .push((index, point));
-                        self.race
-                            .as_mut()
-                            .unwrap()
-                            .tryhisuojaus
-                            .sort_by(|(a, _), (b, _)| a.cmp(b));
+                        if let Some(point) = self.unused_point() {
+                            let race = self.race.as_mut().unwrap();
+                            race.tryhisuojaus.push((index, point));
+                            race.tryhisuojaus.sort_by_key(|(i, _)| *i);
+                        }
                     }
                     None => (),
                 }
+                if legs_changed {
+                    if let Some(race) = &mut self.race {
+                        race.assignments = self.config.roster.assign(&race.classes());
+                    }
+                }
+
+                ui.separator();
+                ui.collapsing("Difficulty settings", |ui| {
+                    self.config.difficulty.ui(ui);
+                });
+                ui.collapsing("Terrain", |ui| {
+                    self.config.terrain.ui(ui);
+                });
+                ui.collapsing("Series", |ui| self.series_ui(ui));
+                ui.collapsing("Roster", |ui| self.roster_ui(ui));
+                ui.collapsing("Stopwatch", |ui| self.stopwatch_ui(ui));
+                ui.collapsing("Results", |ui| self.results_ui(ui));
+                ui.collapsing("Leaderboard", |ui| self.leaderboard_ui(ui));
+                ui.collapsing("Export settings", |ui| {
+                    self.config.export.ui(ui);
+                });
+                ui.collapsing("Text templates", |ui| {
+                    self.config.text_templates.ui(ui);
+                });
+                ui.collapsing("Webhook", |ui| {
+                    self.config.webhook.ui(ui);
+                });
             }
             State::Config(race_points) => {
-                let race_points = race_points.clone();
                 ui.label("Create new checkpoints by left clicking on a location on the map and delete existing ones by left clicking on them.");
+                ui.label(format!(
+                    "Checkpoints used in the last {} races are drawn in blue, fading to red as they get older.",
+                    self.config.freshness.window
+                ));
+                if let Some(terrain) = &self.terrain {
+                    let map_size = Vec2::new(self.gtav_map.width(), self.gtav_map.height());
+                    let count = |kind| {
+                        race_points
+                            .iter()
+                            .filter(|(point, _)| terrain.at(*point, map_size) == kind)
+                            .count()
+                    };
+                    ui.label(format!(
+                        "{} checkpoints are in water (ringed in light blue) and {} in restricted areas (ringed in orange).",
+                        count(Terrain::Water),
+                        count(Terrain::Restricted)
+                    ));
+                }
+                ui.separator();
+                ui.collapsing("Checkpoint names", |ui| {
+                    for (i, (_, name)) in race_points.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("{}: ", i + 1));
+                            ui.text_edit_singleline(name);
+                        });
+                    }
+                });
                 ui.separator();
+                let race_points = race_points.clone();
                 ui.horizontal(|ui| {
                     if ui.button("Save").clicked() {
-                        self.config.race_points = race_points;
-                        fs::write(
-                            &self.config_path,
-                            ron::ser::to_string(&self.config).unwrap(),
-                        )
-                        .unwrap();
+                        self.config.map_mut().race_points =
+                            race_points.iter().map(|(point, _)| *point).collect();
+                        self.config.map_mut().point_names = race_points
+                            .into_iter()
+                            .filter(|(_, name)| !name.is_empty())
+                            .collect();
+                        self.save_config();
+                    }
+                    if ui.button("Back").clicked() {
+                        self.state = State::Idle;
+                    }
+                });
+            }
+            State::Style(style) => {
+                ui.label("Changes are previewed on the map, the last generated race is used if there is one.");
+                ui.separator();
+                style.ui(ui);
+                ui.separator();
+                let style = style.clone();
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        self.config.style = style;
+                        self.save_config();
+                    }
+                    if ui.button("Reset to defaults").clicked() {
+                        self.state = State::Style(Style::default());
                     }
                     if ui.button("Back").clicked() {
                         self.state = State::Idle;
@@ -583,86 +1231,1219 @@ impl RuntimeData {
                         self.state = State::Race(RaceState::default());
                     }
                     if ui.button("Configure checkpoints").clicked() {
-                        self.state = State::Config(self.config.race_points.clone());
+                        self.state = State::Config(
+                            self.config
+                                .map()
+                                .race_points
+                                .iter()
+                                .map(|point| {
+                                    (
+                                        *point,
+                                        self.config
+                                            .map()
+                                            .point_name(*point)
+                                            .unwrap_or_default()
+                                            .to_string(),
+                                    )
+                                })
+                                .collect(),
+                        );
+                    }
+                    if ui.button("Edit style").clicked() {
+                        self.state = State::Style(self.config.style.clone());
                     }
                 });
-            }
-        }
 
-        ui.min_rect()
-    }
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.share_code).hint_text("Share code"),
+                    );
+                    if ui
+                        .add_enabled(
+                            !self.share_code.trim().is_empty(),
+                            egui::Button::new("Import share code"),
+                        )
+                        .clicked()
+                    {
+                        self.import_share_code();
+                    }
+                });
+
+                ui.separator();
 
-    fn race_image(&self, race: &Race) -> Image {
-        let render_target =
-            render_target(self.gtav_map.width() as u32, self.gtav_map.height() as u32);
+                if self.host.is_none() && self.guest.is_none() {
+                    ui.collapsing("Lobby", |ui| self.lobby_ui(ui));
+                }
+                ui.collapsing("Leaderboard", |ui| self.leaderboard_ui(ui));
 
-        set_camera(&Camera2D {
-            target: Vec2::new(self.gtav_map.width() / 2.0, self.gtav_map.height() / 2.0),
-            zoom: Vec2::new(2.0 / self.gtav_map.width(), 2.0 / self.gtav_map.height()),
-            render_target: Some(render_target),
-            ..Default::default()
-        });
+                if !self.fixed_config_path {
+                    self.profile_ui(ui);
+                }
+                if ui
+                    .checkbox(
+                        &mut self.config.restore_session,
+                        "Restore session on startup",
+                    )
+                    .changed()
+                {
+                    self.save_config();
+                }
 
-        draw_texture(self.gtav_map, 0.0, 0.0, WHITE);
+                let mut selected_map = self.config.selected_map;
+                let mut reload = false;
+                let mut remove = false;
 
-        self.draw_race(race, 1.0);
+                egui::ComboBox::from_label("Map")
+                    .selected_text(&self.config.map().name)
+                    .show_ui(ui, |ui| {
+                        for (i, map) in self.config.maps.iter().enumerate() {
+                            ui.selectable_value(&mut selected_map, i, &map.name);
+                        }
+                    });
 
-        set_default_camera();
+                ui.collapsing("Map settings", |ui| {
+                    self.config.map_mut().ui(ui);
+                    ui.horizontal(|ui| {
+                        if ui.button("Reload image").clicked() {
+                            reload = true;
+                        }
+                        if ui.button("Add map").clicked() {
+                            self.config.maps.push(MapConfig {
+                                name: "New map".to_string(),
+                                path: Some(String::new()),
+                                race_points: Vec::new(),
+                                ..Default::default()
+                            });
+                            selected_map = self.config.maps.len() - 1;
+                        }
+                        if self.config.maps.len() > 1 && ui.button("Remove map").clicked() {
+                            remove = true;
+                        }
+                        if ui.button("Save").clicked() {
+                            self.save_config();
+                        }
+                    });
+                });
 
-        render_target.texture.get_texture_data()
+                if remove {
+                    self.remove_map();
+                } else if reload || selected_map != self.config.selected_map {
+                    self.select_map(selected_map);
+                }
+            }
+        }
     }
 
-    fn generate_race(&self, mut race_state: RaceState) -> Race {
-        let mut race_points = self.config.race_points.clone();
-        race_points.shuffle();
+    fn series_ui(&mut self, ui: &mut egui::Ui) {
+        let State::Race(race_state) = &mut self.state else {
+            return;
+        };
+        ui.add(egui::Slider::new(&mut race_state.series_length, 2..=10).text("Races"));
+        let race_state = race_state.clone();
+
+        let mut action = None;
+
+        let needed = race_state.series_length * (race_state.length + race_state.n_tryhisuojaus);
+        if ui
+            .add_enabled(
+                race_state.length > 1 && self.config.map().race_points.len() >= needed,
+                egui::Button::new("Generate series"),
+            )
+            .on_disabled_hover_text(format!(
+                "Needs a race length of at least 2 and {} checkpoints in the pool",
+                needed
+            ))
+            .clicked()
+        {
+            action = Some(SeriesAction::Generate);
+        }
 
-        let points = &race_points[0..race_state.length];
-        let tryhisuojaus =
-            &race_points[race_state.length..(race_state.length + race_state.n_tryhisuojaus)];
+        if let Some(series) = &mut self.series {
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(series.current > 0, egui::Button::new("Previous"))
+                    .clicked()
+                {
+                    action = Some(SeriesAction::Select(series.current - 1));
+                }
+                ui.label(format!(
+                    "Race {}/{}",
+                    series.current + 1,
+                    series.races.len()
+                ));
+                if ui
+                    .add_enabled(
+                        series.current + 1 < series.races.len(),
+                        egui::Button::new("Next"),
+                    )
+                    .clicked()
+                {
+                    action = Some(SeriesAction::Select(series.current + 1));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut series.name);
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Export all images").clicked() {
+                    action = Some(SeriesAction::ExportImages);
+                }
+                if ui.button("Save series").clicked() {
+                    action = Some(SeriesAction::Save);
+                }
+                if ui.button("Close series").clicked() {
+                    action = Some(SeriesAction::Close);
+                }
+            });
+        }
 
-        race_state.classes.truncate(race_state.length - 1);
-        race_state.classes.shuffle();
+        egui::ComboBox::from_label("Open saved series")
+            .selected_text("")
+            .show_ui(ui, |ui| {
+                for name in Series::saved() {
+                    if ui.selectable_label(false, &name).clicked() {
+                        action = Some(SeriesAction::Open(name));
+                    }
+                }
+            });
+
+        match action {
+            Some(SeriesAction::Generate) => {
+                let (series, fits) = self.generate_series(race_state);
+                self.record_history(&series.races);
+                self.series = Some(series);
+                self.show_series_race(0);
+                if !fits {
+                    self.status = Some(Status::Info(TERRAIN_MISFIT.to_string()));
+                }
+            }
+            Some(SeriesAction::Select(i)) => {
+                self.store_series_race();
+                self.show_series_race(i);
+            }
+            Some(SeriesAction::ExportImages) => self.export_series_images(),
+            Some(SeriesAction::Save) => {
+                self.store_series_race();
+                if let Some(series) = &self.series {
+                    self.status = Some(match series.save() {
+                        Ok(path) => Status::Info(format!("Saved series to {}", path.display())),
+                        Err(why) => Status::Error(why),
+                    });
+                }
+            }
+            Some(SeriesAction::Open(name)) => match Series::load(&name) {
+                // Series saved before the map was recorded are opened on any map
+                Ok(series) if !series.map.is_empty() && series.map != self.config.map().name => {
+                    self.status = Some(Status::Error(format!(
+                        "{} was made on the map {:?}, select it to open the series",
+                        name, series.map
+                    )));
+                }
+                Ok(series) => {
+                    let current = series.current;
+                    self.series = Some(series);
+                    self.show_series_race(current);
+                }
+                Err(why) => self.status = Some(Status::Error(why)),
+            },
+            Some(SeriesAction::Close) => self.series = None,
+            None => (),
+        }
+    }
 
-        let mut tryhisuojaus = tryhisuojaus
+    /// Keep edits to the shown race in the series
+    fn store_series_race(&mut self) {
+        if let (Some(series), Some(race)) = (&mut self.series, &self.race) {
+            if let Some(stored) = series.races.get_mut(series.current) {
+                *stored = race.clone();
+            }
+        }
+    }
+
+    fn show_series_race(&mut self, index: usize) {
+        if let Some(series) = &mut self.series {
+            if let Some(race) = series.races.get(index) {
+                series.current = index;
+                self.race = Some(race.clone());
+            }
+        }
+    }
+
+    fn export_series_images(&mut self) {
+        self.store_series_race();
+        let Some(series) = &self.series else {
+            return;
+        };
+
+        let result = series
+            .races
             .iter()
-            .map(|point| (rand::gen_range(0, race_state.length - 2), *point))
-            .collect::<Vec<_>>();
-        tryhisuojaus.sort_by(|(a, _), (b, _)| a.cmp(b));
+            .enumerate()
+            .map(|(i, race)| {
+                self.config.export.save(
+                    rgba_image(self.race_image(race, &self.config.export)),
+                    race.seed,
+                    Some(i + 1),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>();
+
+        self.status = Some(match result {
+            Ok(paths) => {
+                self.save_config_if_changed();
+                Status::Info(format!(
+                    "Saved {} images to {}",
+                    paths.len(),
+                    self.config.export.directory
+                ))
+            }
+            Err(why) => Status::Error(why),
+        });
+    }
 
-        Race {
-            checkpoints: points
+    fn roster_ui(&mut self, ui: &mut egui::Ui) {
+        if let Some(race) = &mut self.race {
+            if !race.assignments.is_empty() {
+                egui::Grid::new("assignments").striped(true).show(ui, |ui| {
+                    ui.label("Player");
+                    for leg in 0..race.legs() {
+                        ui.label(format!("{} - {}", leg + 1, leg + 2));
+                    }
+                    ui.end_row();
+
+                    for (player, assigned) in &race.assignments {
+                        ui.label(player);
+                        for assigned in assigned {
+                            ui.label(assigned);
+                        }
+                        ui.end_row();
+                    }
+                });
+            }
+            if ui.button("Reassign").clicked() {
+                race.assignments = self.config.roster.assign(&race.classes());
+            }
+            ui.separator();
+        }
+
+        self.config.roster.ui(ui);
+    }
+
+    fn stopwatch_ui(&mut self, ui: &mut egui::Ui) {
+        let Some(race) = &mut self.race else {
+            ui.label("Generate a race first");
+            return;
+        };
+
+        if race.stopwatch.ui(ui, &race.classes()) {
+            race.results = race.stopwatch.finishes(race.legs());
+        }
+    }
+
+    fn results_ui(&mut self, ui: &mut egui::Ui) {
+        let Some(race) = &mut self.race else {
+            ui.label("Generate a race first");
+            return;
+        };
+
+        results::finishes_ui(ui, &mut race.results, self.config.results.points_system());
+
+        if ui
+            .add_enabled(
+                !race.results.is_empty(),
+                egui::Button::new("Record in leaderboard"),
+            )
+            .on_hover_text("Recording the race again replaces its earlier results")
+            .clicked()
+        {
+            self.leaderboard.record(race);
+            self.save_leaderboard("Recorded results in the leaderboard");
+        }
+    }
+
+    fn leaderboard_ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Points systems", |ui| self.config.results.ui(ui));
+
+        let Some(system) = self.config.results.points_system() else {
+            return;
+        };
+
+        egui::Grid::new("leaderboard").striped(true).show(ui, |ui| {
+            ui.label("");
+            ui.label("Player");
+            ui.label("Races");
+            ui.label("Wins");
+            ui.label("Points");
+            ui.end_row();
+
+            for (i, standing) in self.leaderboard.standings(system).iter().enumerate() {
+                ui.label(format!("{}.", i + 1));
+                ui.label(&standing.player);
+                ui.label(standing.races.to_string());
+                ui.label(standing.wins.to_string());
+                ui.label(standing.points.to_string());
+                ui.end_row();
+            }
+        });
+
+        ui.label(format!("{} races recorded", self.leaderboard.races.len()));
+        if !self.leaderboard.races.is_empty()
+            && ui
+                .button("Clear leaderboard")
+                .on_hover_text("Double click to remove all recorded results")
+                .double_clicked()
+        {
+            self.leaderboard.races.clear();
+            self.save_leaderboard("Cleared the leaderboard");
+        }
+    }
+
+    fn save_leaderboard(&mut self, message: &str) {
+        // Remember the points systems along with the results
+        self.save_config();
+        self.status = Some(match self.leaderboard.save(&paths::leaderboard_path()) {
+            Ok(()) => Status::Info(message.to_string()),
+            Err(why) => Status::Error(why),
+        });
+    }
+
+    /// Hosting or joining a race shared over the network
+    fn lobby_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut self.config.lobby.port).prefix("Port "));
+            if ui.button("Host").clicked() {
+                match lobby::Host::start(self.config.lobby.port) {
+                    Ok(host) => {
+                        self.host = Some(host);
+                        self.save_config_if_changed();
+                    }
+                    Err(why) => self.status = Some(Status::Error(why)),
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.config.lobby.host_address)
+                    .hint_text("host:port"),
+            );
+            if ui
+                .add_enabled(
+                    !self.config.lobby.host_address.trim().is_empty(),
+                    egui::Button::new("Join"),
+                )
+                .clicked()
+            {
+                self.guest = Some(lobby::Guest::join(&self.config.lobby.host_address));
+                self.save_config_if_changed();
+            }
+        });
+    }
+
+    fn lobby_status_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            if let Some(host) = &self.host {
+                match host.address() {
+                    Some(address) => ui.label(format!("Hosting at {}", address)),
+                    None => ui
+                        .label(format!("Hosting at 0.0.0.0:{}", host.port))
+                        .on_hover_text(
+                            "No network route was found, guests join with this machine's \
+                             address on the local network",
+                        ),
+                };
+                if ui.button("Stop hosting").clicked() {
+                    self.host = None;
+                }
+            }
+            if let Some(guest) = &self.guest {
+                ui.label(format!("Following the race at {}", guest.address));
+                if ui.button("Leave").clicked() {
+                    self.guest = None;
+                }
+            }
+        });
+    }
+
+    /// Publish the race when hosting, or take the host's race when joined
+    fn update_lobby(&mut self) {
+        let needs_update = match &mut self.host {
+            Some(host) => host.needs_update(self.race.as_ref()),
+            None => false,
+        };
+        if needs_update {
+            let image = self
+                .race
+                .as_ref()
+                .map(|race| rgba_image(self.race_image(race, &lobby::image_settings())));
+            let map = self.config.map().name.clone();
+            if let Some(host) = &mut self.host {
+                host.publish(self.race.as_ref(), &map, image);
+            }
+        }
+
+        let Some(update) = self.guest.as_ref().and_then(|guest| guest.poll()) else {
+            return;
+        };
+        match update {
+            Ok(update) => {
+                if update.map != self.config.map().name {
+                    self.status = Some(Status::Error(format!(
+                        "The host is using the map \"{}\", checkpoints may be off",
+                        update.map
+                    )));
+                }
+                if matches!(self.state, State::Idle) {
+                    self.state = State::Race(RaceState::from_race(&update.race));
+                }
+                self.race = Some(update.race);
+            }
+            Err(why) => self.status = Some(Status::Error(why)),
+        }
+    }
+
+    fn profile_ui(&mut self, ui: &mut egui::Ui) {
+        let mut profile = self.profile.clone();
+
+        egui::ComboBox::from_label("Profile")
+            .selected_text(self.profile.as_deref().unwrap_or("Default"))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut profile, None, "Default");
+                for name in paths::profiles() {
+                    ui.selectable_value(&mut profile, Some(name.clone()), name);
+                }
+            });
+
+        ui.horizontal(|ui| {
+            let id = ui.make_persistent_id("new_profile");
+            let mut name = ui.data_mut(|data| data.get_temp::<String>(id).unwrap_or_default());
+            ui.text_edit_singleline(&mut name);
+            let valid = paths::valid_name(&name);
+            if ui
+                .add_enabled(valid, egui::Button::new("New profile"))
+                .clicked()
+            {
+                profile = Some(std::mem::take(&mut name));
+            }
+            ui.data_mut(|data| data.insert_temp(id, name));
+        });
+
+        if profile != self.profile {
+            self.switch_profile(profile);
+        }
+    }
+
+    fn switch_profile(&mut self, profile: Option<String>) {
+        self.save_session();
+
+        self.config_path = paths::config_path(profile.as_deref());
+        self.session_path = paths::session_path(profile.as_deref(), None);
+        self.saved_session.clear();
+        self.profile = profile;
+        let (config, error) = Config::load(&self.config_path);
+        self.saved_config = config.to_ron().unwrap_or_default();
+        self.config = config;
+        self.config_error = error;
+        // Write new profiles right away so they show up in the list, saving is skipped if
+        // that would overwrite a file that couldn't be loaded
+        self.save_config();
+
+        // Fall back to the bundled map like on startup, the profile's maps stay as they are
+        let texture = match self.config.map().load_texture(&self.data_dirs) {
+            Ok(texture) => texture,
+            Err(why) => {
+                self.status = Some(Status::Error(why));
+                MapConfig::default().load_texture(&self.data_dirs).unwrap()
+            }
+        };
+        self.gtav_map.delete();
+        self.gtav_map = texture;
+        self.load_map_data();
+
+        let session = if self.config.restore_session {
+            Session::load(&self.session_path)
+        } else {
+            None
+        };
+        (self.state, self.race, self.series) = Session::restore(session, self.config.selected_map);
+    }
+
+    /// Show the map at `index`, staying on the current one if its image can't be loaded
+    fn select_map(&mut self, index: usize) -> bool {
+        let texture = match self.config.maps[index].load_texture(&self.data_dirs) {
+            Ok(texture) => texture,
+            Err(why) => {
+                self.status = Some(Status::Error(why));
+                return false;
+            }
+        };
+
+        self.config.selected_map = index;
+        self.gtav_map.delete();
+        self.gtav_map = texture;
+        // Races are placed in the pixel space of the map they were made on
+        self.race = None;
+        self.series = None;
+        self.status = None;
+        self.save_config();
+        self.load_map_data();
+        true
+    }
+
+    /// Remove the selected map once the first of the others is shown
+    fn remove_map(&mut self) {
+        let removed = self.config.selected_map;
+        let next = if removed == 0 { 1 } else { 0 };
+
+        if self.select_map(next) {
+            self.config.maps.remove(removed);
+            // Whichever map was shown is the first one now
+            self.config.selected_map = 0;
+            self.save_config();
+        }
+    }
+
+    /// Road graph and terrain mask of the selected map
+    fn load_map_data(&mut self) {
+        self.roads = match self.config.map().load_roads(&self.data_dirs) {
+            Ok(roads) => roads,
+            Err(why) => {
+                self.status = Some(Status::Error(why));
+                None
+            }
+        };
+        self.terrain = match self.config.map().load_terrain(&self.data_dirs) {
+            Ok(terrain) => terrain,
+            Err(why) => {
+                self.status = Some(Status::Error(why));
+                None
+            }
+        };
+    }
+
+    fn save_image(&mut self) {
+        let Some(race) = &self.race else {
+            return;
+        };
+
+        let image = rgba_image(self.race_image(race, &self.config.export));
+
+        let result = self.config.export.save(image, race.seed, None);
+        self.status = Some(match result {
+            Ok(path) => {
+                self.save_config_if_changed();
+                Status::Info(format!("Saved image to {}", path.display()))
+            }
+            Err(why) => Status::Error(why),
+        });
+    }
+
+    fn copy_image(&mut self) {
+        let Some(race) = &self.race else {
+            return;
+        };
+
+        let image = self.race_image(race, &self.config.export);
+
+        let result = match &mut self.clipboard {
+            Some(clipboard) => clipboard
+                .set_image(arboard::ImageData {
+                    width: image.width(),
+                    height: image.height(),
+                    bytes: Cow::from_iter(image.get_image_data().iter().flatten().cloned()),
+                })
+                .map_err(|why| why.to_string()),
+            None => Err("no clipboard available".to_string()),
+        };
+
+        self.status = Some(match result {
+            Ok(()) => Status::Info("Copied map to clipboard".to_string()),
+            // Save the image to a file instead so it's not lost
+            Err(why) => match self.config.export.save(rgba_image(image), race.seed, None) {
+                Ok(path) => Status::Error(format!(
+                    "Could not copy map to clipboard ({}), saved it to {} instead",
+                    why,
+                    path.display()
+                )),
+                Err(save_why) => Status::Error(format!(
+                    "Could not copy map to clipboard ({}) or save it: {}",
+                    why, save_why
+                )),
+            },
+        });
+    }
+
+    fn copy_text(&mut self) {
+        let Some(race) = &self.race else {
+            return;
+        };
+
+        let text = self
+            .config
+            .text_templates
+            .render(race, self.config.map(), self.roads.as_ref());
+
+        let result = match &mut self.clipboard {
+            Some(clipboard) => clipboard.set_text(text).map_err(|why| why.to_string()),
+            None => Err("no clipboard available".to_string()),
+        };
+
+        self.status = Some(match result {
+            Ok(()) => {
+                // Remember the templates the text was made with
+                self.save_config_if_changed();
+                Status::Info("Copied race as text".to_string())
+            }
+            Err(why) => Status::Error(format!("Could not copy text to clipboard: {}", why)),
+        });
+    }
+
+    fn copy_share_code(&mut self) {
+        let Some(race) = &self.race else {
+            return;
+        };
+
+        let code = share::encode(race, &self.config.map().race_points);
+
+        let result = match &mut self.clipboard {
+            Some(clipboard) => clipboard.set_text(code).map_err(|why| why.to_string()),
+            None => Err("no clipboard available".to_string()),
+        };
+
+        self.status = Some(match result {
+            Ok(()) => Status::Info("Copied share code".to_string()),
+            Err(why) => Status::Error(format!("Could not copy share code to clipboard: {}", why)),
+        });
+    }
+
+    fn import_share_code(&mut self) {
+        match share::decode(&self.share_code, &self.config.map().race_points) {
+            Ok(race) => {
+                self.state = State::Race(RaceState::from_race(&race));
+                self.race = Some(race);
+                self.share_code.clear();
+                self.status = Some(Status::Info("Imported race from share code".to_string()));
+            }
+            Err(why) => self.status = Some(Status::Error(why)),
+        }
+    }
+
+    /// Send the image and text to the webhook, the result shows up in the status later
+    fn post_race(&mut self) {
+        let Some(race) = &self.race else {
+            return;
+        };
+
+        let text = self
+            .config
+            .text_templates
+            .render(race, self.config.map(), self.roads.as_ref());
+        let export = &self.config.export;
+        let image = match export.encode(rgba_image(self.race_image(race, &self.config.export))) {
+            Ok(image) => image,
+            Err(why) => {
+                self.status = Some(Status::Error(why));
+                return;
+            }
+        };
+        let filename = export
+            .path(race.seed, None)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| format!("race.{}", export.format.extension()));
+
+        self.webhook_post = Some(self.config.webhook.post(
+            text,
+            image,
+            filename,
+            export.format.mime(),
+        ));
+        self.status = Some(Status::Info("Posting race...".to_string()));
+        // Remember the webhook the race was posted to
+        self.save_config_if_changed();
+    }
+
+    fn update_webhook(&mut self) {
+        let Some(post) = &self.webhook_post else {
+            return;
+        };
+
+        let result = match post.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err("Posting was interrupted".to_string()),
+        };
+        self.webhook_post = None;
+        self.status = Some(match result {
+            Ok(()) => Status::Info("Posted race".to_string()),
+            Err(why) => Status::Error(why),
+        });
+    }
+
+    fn save_session(&mut self) {
+        let session = Session {
+            state: self.state.clone(),
+            race: self.race.clone(),
+            series: self.series.clone(),
+            map: self.config.selected_map,
+            window_size: (screen_width(), screen_height()),
+        };
+
+        if let Err(why) = session.save(&self.session_path, &mut self.saved_session) {
+            self.status = Some(Status::Error(why));
+        }
+    }
+
+    /// Save the config, unless its file couldn't be loaded or the error hasn't been seen yet
+    fn save_config(&mut self) {
+        if self.config_error.is_some() || self.config.read_only() {
+            return;
+        }
+        match self.config.save(&self.config_path) {
+            Ok(()) => self.saved_config = self.config.to_ron().unwrap_or_default(),
+            Err(why) => self.status = Some(Status::Error(why)),
+        }
+    }
+
+    /// Remember settings an action was done with, leaving the config alone if they're the ones
+    /// already saved
+    fn save_config_if_changed(&mut self) {
+        if self.config.to_ron().ok().as_ref() != Some(&self.saved_config) {
+            self.save_config();
+        }
+    }
+
+    /// Sample race from the first points of the pool for previewing styles
+    fn preview_race(&self) -> Option<Race> {
+        let points = &self.config.map().race_points;
+        if points.len() < 2 {
+            return None;
+        }
+
+        let length = points.len().min(4);
+        let classes = ["Super", "Sports", "Muscle"];
+
+        Some(Race {
+            checkpoints: points[..length]
                 .iter()
-                .copied()
                 .enumerate()
-                .map(|(i, point)| match race_state.classes.get(i) {
-                    Some(class) => (point, Some(class.clone())),
-                    None => (point, None),
+                .map(|(i, point)| {
+                    (
+                        *point,
+                        classes
+                            .get(i)
+                            .filter(|_| i + 1 < length)
+                            .map(|class| class.to_string()),
+                    )
                 })
                 .collect(),
-            tryhisuojaus,
+            tryhisuojaus: points
+                .get(length)
+                .map(|point| (0, *point))
+                .into_iter()
+                .collect(),
+            seed: 0,
+            generated_at: Local::now(),
+            results: Vec::new(),
+            stopwatch: Default::default(),
+            assignments: Vec::new(),
+        })
+    }
+
+    /// Record a stopwatch split for the checkpoint under `point`, if the stopwatch is running
+    fn click_checkpoint(&mut self, point: Point) {
+        let Some(race) = &self.race else {
+            return;
+        };
+        if !race.stopwatch.running() {
+            return;
+        }
+
+        // Labels of nearby checkpoints can overlap, the closest one wins
+        let clicked = self
+            .checkpoint_extents(race, &self.config.style)
+            .iter()
+            .zip(&race.checkpoints)
+            .enumerate()
+            .filter(|(_, (extent, _))| extent.contains(Vec2::from(point)))
+            .min_by(|(_, (_, (a, _))), (_, (_, (b, _)))| {
+                a.distance_to((point.x, point.y))
+                    .total_cmp(&b.distance_to((point.x, point.y)))
+            })
+            .map(|(i, _)| i);
+
+        if let (Some(checkpoint), Some(race)) = (clicked, &mut self.race) {
+            let legs = race.legs();
+            if let Err(why) = race.stopwatch.reach(checkpoint, legs) {
+                self.status = Some(Status::Info(why));
+            }
         }
     }
 
-    fn random_point(&self, race: &Race) -> Point {
-        **self
-            .config
-            .race_points
+    /// Area covered by each checkpoint with its labels in map pixels, matching `draw_race`
+    fn checkpoint_extents(&self, race: &Race, style: &Style) -> Vec<Rect> {
+        race.checkpoints
             .iter()
-            .filter(|point| {
-                !(race
-                    .checkpoints
-                    .iter()
-                    .any(|(checkpoint, _)| *checkpoint == **point)
-                    || race
-                        .tryhisuojaus
-                        .iter()
-                        .any(|(_, checkpoint)| *checkpoint == **point))
+            .enumerate()
+            .map(|(i, (point, class))| {
+                self.circle_extent(*point, style)
+                    .combine_with(self.label_extent(&format!("{}", i + 1), *point, 0.0, style))
+                    .combine_with(self.label_extent(
+                        class.as_deref().unwrap_or("Goal"),
+                        *point,
+                        style.text_size * 0.7,
+                        style,
+                    ))
             })
-            .collect::<Vec<_>>()
-            .choose()
-            .unwrap()
+            .collect()
+    }
+
+    /// Areas covered by the checkpoints, tryhisuojaus and their labels in map pixels
+    fn race_extents(&self, race: &Race, style: &Style) -> Vec<Rect> {
+        let mut extents = self.checkpoint_extents(race, style);
+        for (i, (_, point)) in race.tryhisuojaus.iter().enumerate() {
+            extents.push(
+                self.circle_extent(*point, style)
+                    .combine_with(self.label_extent(
+                        &format!("{}", i + 1),
+                        *point,
+                        style.text_size / 2.0,
+                        style,
+                    )),
+            );
+        }
+        extents
+    }
+
+    fn circle_extent(&self, point: Point, style: &Style) -> Rect {
+        let radius = style.circle_radius + style.circle_thickness;
+        Rect::new(
+            point.x - radius,
+            point.y - radius,
+            radius * 2.0,
+            radius * 2.0,
+        )
+    }
+
+    /// Label of a checkpoint at `point` with its baseline `baseline` below it
+    fn label_extent(&self, text: &str, point: Point, baseline: f32, style: &Style) -> Rect {
+        let font = self.fonts.get(style.font).fill;
+        let dimensions = measure_text(text, Some(font), style.text_size as u16, 1.0);
+        Rect::new(
+            point.x + LABEL_OFFSET,
+            point.y + baseline - dimensions.offset_y,
+            dimensions.width,
+            dimensions.height,
+        )
+    }
+
+    fn race_image(&self, race: &Race, export: &ExportConfig) -> Image {
+        let region = export.region(self.map_size(), self.race_extents(race, &self.config.style));
+
+        // Draw at the final resolution instead of scaling the camera so text stays sharp
+        let scale = export.scale.max(1) as f32;
+        let size = region.size() * scale;
+        let map_view = MapView {
+            offset: -region.point() * scale,
+            scale_factor: scale,
+        };
+
+        let render_target = render_target(size.x as u32, size.y as u32);
+
+        set_camera(&Camera2D {
+            target: size / 2.0,
+            zoom: Vec2::new(2.0 / size.x, 2.0 / size.y),
+            render_target: Some(render_target),
+            ..Default::default()
+        });
+
+        clear_background(BLACK);
+        draw_texture_ex(
+            self.gtav_map,
+            map_view.offset.x,
+            map_view.offset.y,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(self.map_size() * scale),
+                ..Default::default()
+            },
+        );
+
+        self.draw_race(race, map_view, &self.config.style);
+
+        if export.legend {
+            self.draw_legend(race, scale);
+        }
+
+        set_default_camera();
+
+        render_target.texture.get_texture_data()
+    }
+
+    /// Summary of the race in the top left corner of an exported image
+    fn draw_legend(&self, race: &Race, scale: f32) {
+        let style = &self.config.style;
+        let meters_per_pixel = self.config.map().meters_per_pixel;
+
+        let mut lines = vec![format!(
+            "Seed {}, {}",
+            race.seed,
+            race.generated_at.format("%Y-%m-%d %H:%M")
+        )];
+        for leg in 0..race.legs() {
+            let mut line = format!(
+                "{} - {}: {}",
+                leg + 1,
+                leg + 2,
+                format_distance(race.leg_length(leg, self.roads.as_ref()) * meters_per_pixel)
+            );
+            if let Some(class) = race.checkpoints[leg].1.as_ref().filter(|c| !c.is_empty()) {
+                line.push_str(&format!(", {}", class));
+            }
+            lines.push(line);
+        }
+        for (i, (index, _)) in race.tryhisuojaus.iter().enumerate() {
+            lines.push(format!(
+                "Tryhisuojaus {}: {} - {}",
+                i + 1,
+                index + 1,
+                index + 2
+            ));
+        }
+        lines.push(format!(
+            "Total {}",
+            format_distance(race.length(self.roads.as_ref()) * meters_per_pixel)
+        ));
+        for (player, assigned) in &race.assignments {
+            lines.push(format!("{}: {}", player, assigned.join(" / ")));
+        }
+
+        let font = self.fonts.get(style.font).fill;
+        let font_size = (style.text_size * 0.4 * scale) as u16;
+        let line_height = font_size as f32 * 1.2;
+        let margin = 20.0 * scale;
+        let width = lines
+            .iter()
+            .map(|line| measure_text(line, Some(font), font_size, 1.0).width)
+            .fold(0.0, f32::max);
+
+        draw_rectangle(
+            margin,
+            margin,
+            width + margin * 2.0,
+            line_height * lines.len() as f32 + margin * 2.0,
+            Color::from_rgba(0, 0, 0, 180),
+        );
+
+        for (i, line) in lines.iter().enumerate() {
+            self.draw_bold_text(
+                line,
+                margin * 2.0,
+                margin * 2.0 + line_height * (i as f32 + 0.8),
+                font_size,
+                color(style.text_color),
+                color(style.text_outline_color),
+                style,
+            );
+        }
+    }
+
+    /// Generate a race, also telling whether it keeps to the terrain settings
+    fn generate_race(&self, mut race_state: RaceState) -> (Race, bool) {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or_default();
+        rand::srand(seed);
+
+        race_state.classes.truncate(race_state.length - 1);
+
+        let mut make = || {
+            let race_points = self.fresh_points(&[], race_state.length + race_state.n_tryhisuojaus);
+            let (points, tryhisuojaus) = race_points.split_at(race_state.length);
+            let mut points = points.to_vec();
+            race_state.order.apply(&mut points, &|from, to| {
+                leg_distance(from, to, self.roads.as_ref())
+            });
+
+            race_state.classes.shuffle();
+            Race::new(&points, tryhisuojaus, &race_state.classes, seed)
+        };
+
+        let (mut race, fits) = match race_state.difficulty.target() {
+            None => self.fit_terrain(&mut make),
+            // Keep the candidate closest to the target, preferring ones that fit the terrain.
+            // The candidates stand in for the terrain attempts, so no more races are made.
+            Some(target) => {
+                let (mut race, fits, _) = (0..self.config.difficulty.candidates.max(1))
+                    .map(|_| {
+                        let race = make();
+                        let score = difficulty::score(
+                            &race,
+                            self.config.map().meters_per_pixel,
+                            self.roads.as_ref(),
+                            &self.config.difficulty,
+                        );
+                        let fits = self.fits_terrain(&race);
+                        (race, fits, (score.total() - target).abs())
+                    })
+                    .min_by(|(_, a_fits, a), (_, b_fits, b)| {
+                        b_fits.cmp(a_fits).then(a.total_cmp(b))
+                    })
+                    .unwrap();
+                self.assign_water_classes(&mut race);
+                (race, fits)
+            }
+        };
+        race.assignments = self.config.roster.assign(&race.classes());
+        (race, fits)
+    }
+
+    /// Races for a series, each using its own part of the pool.
+    /// Also tells whether they all keep to the terrain settings.
+    fn generate_series(&self, mut race_state: RaceState) -> (Series, bool) {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or_default();
+        rand::srand(seed);
+
+        let points_per_race = race_state.length + race_state.n_tryhisuojaus;
+
+        race_state.classes.truncate(race_state.length - 1);
+        race_state.classes.shuffle();
+
+        let mut races = Vec::new();
+        let mut all_fit = true;
+
+        for i in 0..race_state.series_length {
+            let used = series::points(&races);
+            // Stop when the pool runs out
+            if self.fresh_points(&used, points_per_race).len() < points_per_race {
+                break;
+            }
+
+            let (mut race, fits) = self.fit_terrain(|| {
+                let race_points = self.fresh_points(&used, points_per_race);
+                let (points, tryhisuojaus) = race_points.split_at(race_state.length);
+                let mut points = points.to_vec();
+                race_state.order.apply(&mut points, &|from, to| {
+                    leg_distance(from, to, self.roads.as_ref())
+                });
+                Race::new(
+                    &points,
+                    tryhisuojaus,
+                    &series::rotated_classes(&race_state.classes, i),
+                    seed.wrapping_add(i as u64),
+                )
+            });
+            race.assignments = self.config.roster.assign(&race.classes());
+            all_fit &= fits;
+            races.push(race);
+        }
+
+        (Series::new(races, &self.config.map().name), all_fit)
+    }
+
+    /// Retry `make` until its race has no legs to avoid, then give legs crossing water their
+    /// classes. Also tells whether the race fits, the last try is kept if none did.
+    fn fit_terrain(&self, mut make: impl FnMut() -> Race) -> (Race, bool) {
+        let mut race = make();
+        let mut fits = self.fits_terrain(&race);
+        for _ in 1..TERRAIN_ATTEMPTS {
+            if fits {
+                break;
+            }
+            race = make();
+            fits = self.fits_terrain(&race);
+        }
+
+        self.assign_water_classes(&mut race);
+        (race, fits)
+    }
+
+    /// What each leg of the race passes through, empty if the map has no terrain mask
+    fn crossings(&self, race: &Race) -> Vec<Crossing> {
+        let Some(terrain) = &self.terrain else {
+            return Vec::new();
+        };
+
+        (0..race.legs())
+            .map(|leg| leg_crossing(race, leg, terrain, self.roads.as_ref(), self.map_size()))
+            .collect()
+    }
+
+    /// Whether no leg of the race crosses terrain it should avoid
+    fn fits_terrain(&self, race: &Race) -> bool {
+        let config = &self.config.terrain;
+
+        self.crossings(race).iter().all(|crossing| {
+            !(config.avoid_restricted && crossing.restricted
+                || config.water_legs == WaterLegs::Avoid && config.crosses_water(crossing))
+        })
+    }
+
+    /// Give legs crossing water one of the water classes, if set to
+    fn assign_water_classes(&self, race: &mut Race) {
+        let config = &self.config.terrain;
+        if config.water_legs != WaterLegs::WaterClasses {
+            return;
+        }
+
+        for (leg, crossing) in self.crossings(race).iter().enumerate() {
+            if config.crosses_water(crossing) {
+                if let Some(class) = config.water_classes.choose() {
+                    race.checkpoints[leg].1 = Some(class.clone());
+                }
+            }
+        }
+    }
+
+    /// A pool point the current race doesn't use yet, `None` with a status if there is none
+    fn unused_point(&mut self) -> Option<Point> {
+        let race = self.race.as_ref()?;
+        let used = race
+            .checkpoints
+            .iter()
+            .map(|(point, _)| *point)
+            .chain(race.tryhisuojaus.iter().map(|(_, point)| *point))
+            .collect::<Vec<_>>();
+
+        let point = self.fresh_points(&used, 1).first().copied();
+        if point.is_none() {
+            self.status = Some(Status::Info(
+                "Every checkpoint in the pool is already used in this race".to_string(),
+            ));
+        }
+        point
+    }
+
+    /// Random points from the pool, favoring ones that weren't used lately
+    fn fresh_points(&self, exclude: &[Point], count: usize) -> Vec<Point> {
+        let map = self.config.map();
+        let pool = map
+            .race_points
+            .iter()
+            .filter(|point| !exclude.contains(point))
+            .copied()
+            .collect::<Vec<_>>();
+        let weights = self
+            .history
+            .weights(&map.name, &pool, &self.config.freshness);
+
+        history::pick_weighted(&pool, &weights, count)
+    }
+
+    fn record_history(&mut self, races: &[Race]) {
+        for race in races {
+            self.history.record(race, &self.config.map().name);
+        }
+        if let Err(why) = self.history.save(&paths::history_path()) {
+            self.status = Some(Status::Error(why));
+        }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn draw_bold_text(
         &self,
         text: &str,
@@ -671,62 +2452,187 @@ impl RuntimeData {
         font_size: u16,
         color: Color,
         outline: Color,
+        style: &Style,
     ) {
-        draw_text_ex(
-            text,
-            x,
-            y,
-            TextParams {
-                font: self.font,
-                font_size,
-                color,
-                ..Default::default()
-            },
-        );
-        draw_text_ex(
-            text,
-            x,
-            y,
-            TextParams {
-                font: self.font_outline,
-                font_size,
-                color: outline,
-                ..Default::default()
-            },
-        );
+        let font = self.fonts.get(style.font);
+        let params = |font, color| TextParams {
+            font,
+            font_size,
+            color,
+            ..Default::default()
+        };
+
+        match font.outline {
+            Some(outline_font) => {
+                draw_text_ex(text, x, y, params(font.fill, color));
+                draw_text_ex(text, x, y, params(outline_font, outline));
+            }
+            None => {
+                // Fake an outline by drawing the text shifted in every direction underneath
+                let width = (font_size as f32 / 25.0).max(1.0);
+                for (dx, dy) in [
+                    (-1.0, -1.0),
+                    (0.0, -1.0),
+                    (1.0, -1.0),
+                    (-1.0, 0.0),
+                    (1.0, 0.0),
+                    (-1.0, 1.0),
+                    (0.0, 1.0),
+                    (1.0, 1.0),
+                ] {
+                    draw_text_ex(
+                        text,
+                        x + dx * width,
+                        y + dy * width,
+                        params(font.fill, outline),
+                    );
+                }
+                draw_text_ex(text, x, y, params(font.fill, color));
+            }
+        }
     }
 }
 
-#[macroquad::main("GTAV Race Gen 2: Electric Boogaloo")]
-async fn main() {
-    let font = load_ttf_font_from_bytes(include_bytes!("../res/VisiaPro-Bold.ttf")).unwrap();
-    let font_outline =
-        load_ttf_font_from_bytes(include_bytes!("../res/VisiaPro-BoldOutline.ttf")).unwrap();
-    let gtav_map = load_texture("assets/gtav-map2.png").await.unwrap();
-    let state = State::Idle;
-    let race: Option<Race> = None;
-    let clipboard = arboard::Clipboard::new().unwrap();
-
-    let config_path = format!("{}/.config/gtav-map-gen.ron", env::var("HOME").unwrap());
-    let config: Config = match fs::read(&config_path) {
-        Ok(data) => ron::de::from_bytes(&data).unwrap_or_default(),
-        Err(_) => Default::default(),
+fn rgba_image(image: Image) -> image::RgbaImage {
+    image::RgbaImage::from_raw(image.width() as u32, image.height() as u32, image.bytes).unwrap()
+}
+
+fn main() {
+    // Parsed before opening the window so --help works without a display
+    let args = Args::parse();
+
+    let config_path = args
+        .config
+        .clone()
+        .unwrap_or_else(|| paths::config_path(args.profile.as_deref()));
+    let (config, config_error) = Config::load(&config_path);
+
+    // Loaded before opening the window to restore its size
+    let session_path = paths::session_path(args.profile.as_deref(), args.config.as_deref());
+    let session = if config.restore_session && !args.fresh {
+        Session::load(&session_path)
+    } else {
+        None
     };
 
+    let mut window_conf = Conf {
+        window_title: "GTAV Race Gen 2: Electric Boogaloo".to_string(),
+        window_resizable: true,
+        ..Default::default()
+    };
+    if let Some(session) = &session {
+        window_conf.window_width = session.window_size.0 as i32;
+        window_conf.window_height = session.window_size.1 as i32;
+    }
+
+    macroquad::Window::from_config(
+        window_conf,
+        app(
+            args,
+            config_path,
+            config,
+            config_error,
+            session_path,
+            session,
+        ),
+    );
+}
+
+async fn app(
+    args: Args,
+    config_path: PathBuf,
+    config: Config,
+    config_error: Option<String>,
+    session_path: PathBuf,
+    session: Option<Session>,
+) {
+    let data_dirs = paths::data_dirs(args.data_dir.as_deref());
+    let fonts = Fonts::load();
+    let (state, race, series) = Session::restore(session, config.selected_map);
+    let (clipboard, status) = match arboard::Clipboard::new() {
+        Ok(clipboard) => (Some(clipboard), None),
+        Err(why) => (
+            None,
+            Some(Status::Error(format!(
+                "Clipboard unavailable ({}), images will be saved to files instead",
+                why
+            ))),
+        ),
+    };
+
+    let fixed_config_path = args.config.is_some();
+    let (gtav_map, status) = match config.map().load_texture(&data_dirs) {
+        Ok(texture) => (texture, status),
+        // Fall back to the bundled map so a missing file doesn't keep the app from starting
+        Err(why) => (
+            MapConfig::default().load_texture(&data_dirs).unwrap(),
+            Some(Status::Error(why)),
+        ),
+    };
+
+    let (roads, status) = match config.map().load_roads(&data_dirs) {
+        Ok(roads) => (roads, status),
+        Err(why) => (None, Some(Status::Error(why))),
+    };
+
+    let (terrain, status) = match config.map().load_terrain(&data_dirs) {
+        Ok(terrain) => (terrain, status),
+        Err(why) => (None, Some(Status::Error(why))),
+    };
+
+    let (leaderboard, status) = match Leaderboard::load(&paths::leaderboard_path()) {
+        (leaderboard, None) => (leaderboard, status),
+        (leaderboard, Some(why)) => (leaderboard, Some(Status::Error(why))),
+    };
+
+    let (history, status) = match History::load(&paths::history_path()) {
+        (history, None) => (history, status),
+        (history, Some(why)) => (history, Some(Status::Error(why))),
+    };
+
+    let saved_config = config.to_ron().unwrap_or_default();
     let mut runtime_data = RuntimeData {
         state,
         config,
-        font,
-        font_outline,
+        fonts,
         gtav_map,
+        roads,
+        terrain,
         race,
+        series,
         config_path,
+        profile: args.profile,
+        fixed_config_path,
+        config_error,
+        saved_config,
+        session_path,
+        saved_session: String::new(),
+        data_dirs,
         clipboard,
+        status,
+        share_code: String::new(),
+        host: None,
+        guest: None,
+        webhook_post: None,
+        leaderboard,
+        history,
     };
 
+    prevent_quit();
+    let mut last_autosave = get_time();
+
     loop {
         runtime_data.run();
 
+        if is_quit_requested() {
+            runtime_data.save_session();
+            break;
+        }
+        if get_time() - last_autosave > AUTOSAVE_INTERVAL {
+            runtime_data.save_session();
+            last_autosave = get_time();
+        }
+
         next_frame().await;
     }
 }
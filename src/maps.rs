@@ -0,0 +1,153 @@
+use std::{fs, path::PathBuf};
+
+use egui_macroquad::egui;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{paths, roads::RoadGraph, terrain::TerrainMask, Point, DEFAULT_POINTS};
+
+const DEFAULT_MAP: &[u8] = include_bytes!("../assets/gtav-map2.png");
+
+/// A map image together with the checkpoint pool placed on it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MapConfig {
+    pub name: String,
+    /// Image file of the map, the bundled road map if not set.
+    /// Relative paths are looked up in the data directories.
+    #[serde(default)]
+    pub path: Option<String>,
+    pub race_points: Vec<Point>,
+    /// Optional names for pool points, looked up by position
+    #[serde(default)]
+    pub point_names: Vec<(Point, String)>,
+    /// Rough in-game distance covered by one pixel of the map image
+    #[serde(default = "default_meters_per_pixel")]
+    pub meters_per_pixel: f32,
+    /// Road graph file for driving distances, looked up like the image
+    #[serde(default)]
+    pub road_graph: Option<String>,
+    /// Image marking water and restricted areas, looked up like the image
+    #[serde(default)]
+    pub terrain_mask: Option<String>,
+}
+
+fn default_meters_per_pixel() -> f32 {
+    7.0
+}
+
+impl Default for MapConfig {
+    fn default() -> Self {
+        Self {
+            name: "Road map".to_string(),
+            path: None,
+            race_points: DEFAULT_POINTS.to_vec(),
+            point_names: Vec::new(),
+            meters_per_pixel: default_meters_per_pixel(),
+            road_graph: None,
+            terrain_mask: None,
+        }
+    }
+}
+
+impl MapConfig {
+    pub fn point_name(&self, point: Point) -> Option<&str> {
+        self.point_names
+            .iter()
+            .find(|(named, _)| *named == point)
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn load_texture(&self, data_dirs: &[PathBuf]) -> Result<Texture2D, String> {
+        let image = match &self.path {
+            Some(path) => {
+                let path = paths::resolve_data_file(path, data_dirs)?;
+                let data = fs::read(&path)
+                    .map_err(|why| format!("Failed to read {}: {}", path.display(), why))?;
+                image::load_from_memory(&data)
+                    .map_err(|why| format!("Failed to decode {}: {}", path.display(), why))?
+            }
+            None => image::load_from_memory(DEFAULT_MAP).unwrap(),
+        }
+        .into_rgba8();
+
+        Ok(Texture2D::from_rgba8(
+            image.width() as u16,
+            image.height() as u16,
+            image.as_raw(),
+        ))
+    }
+
+    /// Road graph of the map, `None` if it has none
+    pub fn load_roads(&self, data_dirs: &[PathBuf]) -> Result<Option<RoadGraph>, String> {
+        match &self.road_graph {
+            Some(path) => {
+                let path = paths::resolve_data_file(path, data_dirs)?;
+                RoadGraph::load(&path).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Terrain mask of the map, `None` if it has none
+    pub fn load_terrain(&self, data_dirs: &[PathBuf]) -> Result<Option<TerrainMask>, String> {
+        match &self.terrain_mask {
+            Some(path) => {
+                let path = paths::resolve_data_file(path, data_dirs)?;
+                TerrainMask::load(&path).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Editor for the name, image and calibration of the map
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("map_settings").show(ui, |ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut self.name);
+            ui.end_row();
+
+            let mut custom_image = self.path.is_some();
+            ui.checkbox(&mut custom_image, "Custom image");
+            match (custom_image, &mut self.path) {
+                (true, Some(path)) => {
+                    ui.text_edit_singleline(path);
+                }
+                (true, None) => self.path = Some(String::new()),
+                (false, _) => self.path = None,
+            }
+            ui.end_row();
+
+            let mut roads = self.road_graph.is_some();
+            ui.checkbox(&mut roads, "Road graph")
+                .on_hover_text("Measure legs along the roads instead of in straight lines");
+            match (roads, &mut self.road_graph) {
+                (true, Some(path)) => {
+                    ui.text_edit_singleline(path);
+                }
+                (true, None) => self.road_graph = Some(String::new()),
+                (false, _) => self.road_graph = None,
+            }
+            ui.end_row();
+
+            let mut terrain = self.terrain_mask.is_some();
+            ui.checkbox(&mut terrain, "Terrain mask")
+                .on_hover_text("Image over the map with water in blue and restricted areas in red");
+            match (terrain, &mut self.terrain_mask) {
+                (true, Some(path)) => {
+                    ui.text_edit_singleline(path);
+                }
+                (true, None) => self.terrain_mask = Some(String::new()),
+                (false, _) => self.terrain_mask = None,
+            }
+            ui.end_row();
+
+            ui.label("Meters per pixel");
+            ui.add(
+                egui::DragValue::new(&mut self.meters_per_pixel)
+                    .speed(0.05)
+                    .clamp_range(0.01..=1000.0),
+            );
+            ui.end_row();
+        });
+    }
+}
@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Point;
+
+/// Passes of 2-opt over the route, it usually settles long before this
+const MAX_PASSES: usize = 50;
+
+/// Order the checkpoints of a race are driven in
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum RouteOrder {
+    /// As picked from the pool
+    #[default]
+    Random,
+    /// Short legs, roughly the shortest way through all checkpoints
+    Shortest,
+    /// Long legs zig-zagging across the map
+    Chaos,
+    SouthToNorth,
+    NorthToSouth,
+    WestToEast,
+    EastToWest,
+}
+
+impl RouteOrder {
+    pub const ALL: &'static [RouteOrder] = &[
+        RouteOrder::Random,
+        RouteOrder::Shortest,
+        RouteOrder::Chaos,
+        RouteOrder::SouthToNorth,
+        RouteOrder::NorthToSouth,
+        RouteOrder::WestToEast,
+        RouteOrder::EastToWest,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RouteOrder::Random => "Random",
+            RouteOrder::Shortest => "Shortest",
+            RouteOrder::Chaos => "Chaos",
+            RouteOrder::SouthToNorth => "South to north",
+            RouteOrder::NorthToSouth => "North to south",
+            RouteOrder::WestToEast => "West to east",
+            RouteOrder::EastToWest => "East to west",
+        }
+    }
+
+    /// Reorder `points` in place, `distance` gives the length of a leg between two points
+    pub fn apply(self, points: &mut [Point], distance: &dyn Fn(Point, Point) -> f32) {
+        match self {
+            RouteOrder::Random => (),
+            RouteOrder::Shortest => {
+                greedy(points, distance, false);
+                two_opt(points, distance, false);
+            }
+            RouteOrder::Chaos => {
+                greedy(points, distance, true);
+                two_opt(points, distance, true);
+            }
+            // Larger y is further south on the map image
+            RouteOrder::SouthToNorth => points.sort_by(|a, b| b.y.total_cmp(&a.y)),
+            RouteOrder::NorthToSouth => points.sort_by(|a, b| a.y.total_cmp(&b.y)),
+            RouteOrder::WestToEast => points.sort_by(|a, b| a.x.total_cmp(&b.x)),
+            RouteOrder::EastToWest => points.sort_by(|a, b| b.x.total_cmp(&a.x)),
+        }
+    }
+}
+
+/// From the first point, always go to the nearest point left, or the farthest if `longest`
+fn greedy(points: &mut [Point], distance: &dyn Fn(Point, Point) -> f32, longest: bool) {
+    for i in 1..points.len() {
+        let from = points[i - 1];
+        let distances = points[i..].iter().map(|point| distance(from, *point));
+        let next = if longest {
+            distances
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        } else {
+            distances
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        };
+        if let Some((next, _)) = next {
+            points.swap(i, i + next);
+        }
+    }
+}
+
+/// Reverse parts of the route while that makes it shorter, or longer if `longest`
+fn two_opt(points: &mut [Point], distance: &dyn Fn(Point, Point) -> f32, longest: bool) {
+    let n = points.len();
+    // Legs are directed, so a reversed part is measured in the direction it is driven
+    let path_length = |points: &[Point]| {
+        points
+            .windows(2)
+            .map(|pair| distance(pair[0], pair[1]))
+            .sum::<f32>()
+    };
+
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+
+        for i in 0..n.saturating_sub(1) {
+            for j in i + 1..n {
+                // Only the reversed part and the legs into and out of it change
+                let start = i.saturating_sub(1);
+                let end = (j + 2).min(n);
+                let before = path_length(&points[start..end]);
+                points[i..=j].reverse();
+                let after = path_length(&points[start..end]);
+
+                let better = if longest {
+                    after > before + 1e-3
+                } else {
+                    after < before - 1e-3
+                };
+                if better {
+                    improved = true;
+                } else {
+                    points[i..=j].reverse();
+                }
+            }
+        }
+
+        if !improved {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn straight(from: Point, to: Point) -> f32 {
+        from.distance_to((to.x, to.y))
+    }
+
+    fn length(points: &[Point]) -> f32 {
+        points
+            .windows(2)
+            .map(|pair| straight(pair[0], pair[1]))
+            .sum()
+    }
+
+    /// Points along a line, shuffled out of order
+    fn scattered() -> Vec<Point> {
+        [0.0, 400.0, 100.0, 300.0, 200.0, 500.0]
+            .into_iter()
+            .map(|x| Point::new(x, 1000.0 - x))
+            .collect()
+    }
+
+    #[test]
+    fn shortest_is_not_longer_than_random() {
+        let mut points = scattered();
+        let random = length(&points);
+        RouteOrder::Shortest.apply(&mut points, &straight);
+
+        assert!(length(&points) <= random);
+        // Along a line the shortest route visits the points in order
+        assert!((length(&points) - 500.0 * 2f32.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn chaos_is_not_shorter_than_random() {
+        let mut points = scattered();
+        let random = length(&points);
+        RouteOrder::Chaos.apply(&mut points, &straight);
+
+        assert!(length(&points) >= random);
+    }
+
+    #[test]
+    fn directions_sort_by_position() {
+        let mut points = scattered();
+
+        RouteOrder::WestToEast.apply(&mut points, &straight);
+        assert!(points.windows(2).all(|pair| pair[0].x <= pair[1].x));
+        RouteOrder::EastToWest.apply(&mut points, &straight);
+        assert!(points.windows(2).all(|pair| pair[0].x >= pair[1].x));
+        // Further south is further down the map image
+        RouteOrder::SouthToNorth.apply(&mut points, &straight);
+        assert!(points.windows(2).all(|pair| pair[0].y >= pair[1].y));
+        RouteOrder::NorthToSouth.apply(&mut points, &straight);
+        assert!(points.windows(2).all(|pair| pair[0].y <= pair[1].y));
+    }
+
+    #[test]
+    fn random_keeps_the_order() {
+        let mut points = scattered();
+        RouteOrder::Random.apply(&mut points, &straight);
+        assert!(points == scattered());
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::de::DeserializeOwned;
+
+/// Directory name used under the XDG base directories
+const APP_DIR: &str = "gtav-race-gen";
+/// Config file name without extension, profiles are appended with a dash
+const CONFIG_NAME: &str = "gtav-map-gen";
+
+/// XDG base directory from `var`, or `home_fallback` under the home directory if it is not set
+fn xdg_dir(var: &str, home_fallback: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(home_fallback)))
+}
+
+/// Directory the config files live in, the current directory if neither
+/// `XDG_CONFIG_HOME` nor `HOME` are set
+pub fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config").unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Whether `name` can be used for a profile or a saved series, it becomes part of a file name
+pub fn valid_name(name: &str) -> bool {
+    !name.trim().is_empty() && !name.contains(['/', '\\']) && !name.contains("..")
+}
+
+pub fn config_path(profile: Option<&str>) -> PathBuf {
+    config_dir().join(match profile {
+        Some(profile) => format!("{}-{}.ron", CONFIG_NAME, profile),
+        None => format!("{}.ron", CONFIG_NAME),
+    })
+}
+
+/// Where the session of a profile is autosaved. A config file given on the command line gets
+/// its own session, named after the file.
+pub fn session_path(profile: Option<&str>, config_file: Option<&Path>) -> PathBuf {
+    let state_dir = xdg_dir("XDG_STATE_HOME", ".local/state")
+        .map(|dir| dir.join(APP_DIR))
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    state_dir.join(match (profile, config_file) {
+        (Some(profile), _) => format!("profile-{}.session.ron", profile),
+        (None, Some(config_file)) => format!(
+            "config-{}.session.ron",
+            config_file
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .unwrap_or_default()
+        ),
+        (None, None) => format!("{}.session.ron", CONFIG_NAME),
+    })
+}
+
+/// Directory for data the app writes itself, the current directory if neither
+/// `XDG_DATA_HOME` nor `HOME` are set
+fn data_home() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+        .map(|dir| dir.join(APP_DIR))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Where saved race series are kept
+pub fn series_dir() -> PathBuf {
+    data_home().join("series")
+}
+
+pub fn leaderboard_path() -> PathBuf {
+    data_home().join("leaderboard.ron")
+}
+
+pub fn history_path() -> PathBuf {
+    data_home().join("history.ron")
+}
+
+/// Write through a temporary file, so a crash mid-write can't leave a truncated file behind
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Copy a file that failed to parse aside with a timestamp, so saving over it doesn't lose it
+/// and it can be recovered by hand
+pub fn backup(path: &Path) -> Result<PathBuf, String> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(
+        ".broken-{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    let backup = PathBuf::from(backup);
+
+    fs::copy(path, &backup)
+        .map(|_| backup)
+        .map_err(|why| why.to_string())
+}
+
+/// Load a RON file, a missing file gives the default.
+///
+/// Problems are returned alongside the default. A file that can't be parsed is copied aside
+/// first so saving doesn't destroy it. The flag is set when the file couldn't be read or
+/// copied, it must not be saved over then.
+pub fn load_ron<T: DeserializeOwned + Default>(path: &Path) -> (T, bool, Option<String>) {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(why) if why.kind() == ErrorKind::NotFound => return (T::default(), false, None),
+        Err(why) => {
+            return (
+                T::default(),
+                true,
+                Some(format!(
+                    "Failed to read {}: {}\nIt won't be saved over.",
+                    path.display(),
+                    why
+                )),
+            )
+        }
+    };
+
+    match ron::de::from_bytes(&data) {
+        Ok(value) => (value, false, None),
+        Err(why) => {
+            let (read_only, backup) = match backup(path) {
+                Ok(backup) => (false, format!("A copy was saved to {}.", backup.display())),
+                Err(why) => (
+                    true,
+                    format!(
+                        "Making a backup failed too ({}), it won't be saved over.",
+                        why
+                    ),
+                ),
+            };
+            (
+                T::default(),
+                read_only,
+                Some(format!(
+                    "Failed to parse {}: {}\n{}",
+                    path.display(),
+                    why,
+                    backup
+                )),
+            )
+        }
+    }
+}
+
+/// Names of the profiles that have a config file
+pub fn profiles() -> Vec<String> {
+    let prefix = format!("{}-", CONFIG_NAME);
+
+    let mut profiles = fs::read_dir(config_dir())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()?
+                        .strip_prefix(&prefix)?
+                        .strip_suffix(".ron")
+                        .map(|profile| profile.to_string())
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    profiles.sort();
+    profiles
+}
+
+/// Directories searched for user supplied files such as map images, most important first
+pub fn data_dirs(data_dir_override: Option<&Path>) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = data_dir_override
+        .map(Path::to_path_buf)
+        .into_iter()
+        .collect();
+
+    dirs.extend(xdg_dir("XDG_DATA_HOME", ".local/share").map(|dir| dir.join(APP_DIR)));
+
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    dirs.extend(
+        data_dirs
+            .split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| PathBuf::from(dir).join(APP_DIR)),
+    );
+
+    dirs
+}
+
+/// Find a relative path in the data directories, falling back to the current directory
+pub fn resolve_data_file(path: &str, data_dirs: &[PathBuf]) -> Result<PathBuf, String> {
+    let path = Path::new(path);
+
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+
+    data_dirs
+        .iter()
+        .map(|dir| dir.join(path))
+        .chain(std::iter::once(path.to_path_buf()))
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| {
+            format!(
+                "Could not find {} in {} or the current directory",
+                path.display(),
+                data_dirs
+                    .iter()
+                    .map(|dir| dir.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "gtav-race-gen-paths-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn sessions_are_named_after_their_config() {
+        let name = |profile, config_file| {
+            session_path(profile, config_file)
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        assert_eq!(name(None, None), format!("{}.session.ron", CONFIG_NAME));
+        assert_eq!(name(Some("league"), None), "profile-league.session.ron");
+        assert_eq!(
+            name(None, Some(Path::new("/tmp/cup.ron"))),
+            "config-cup.session.ron"
+        );
+        // A profile wins over the config file it's stored in
+        assert_eq!(
+            name(Some("league"), Some(Path::new("league.ron"))),
+            "profile-league.session.ron"
+        );
+    }
+
+    #[test]
+    fn missing_file_loads_the_default() {
+        let dir = temp_dir("missing");
+
+        let (value, read_only, problem) = load_ron::<Vec<u32>>(&dir.join("missing.ron"));
+        assert!(value.is_empty() && !read_only && problem.is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broken_file_is_backed_up() {
+        let dir = temp_dir("broken");
+        let path = dir.join("broken.ron");
+        fs::write(&path, "not ron").unwrap();
+
+        let (value, read_only, problem) = load_ron::<Vec<u32>>(&path);
+        assert!(value.is_empty() && !read_only);
+        assert!(problem.is_some_and(|problem| problem.contains("A copy was saved")));
+        let backup = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_name().to_string_lossy().contains(".broken-"))
+            .unwrap();
+        assert_eq!(fs::read_to_string(backup.path()).unwrap(), "not ron");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn atomic_write_replaces_the_file() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("nested").join("file.ron");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        // Only the file itself is left, the temporary one was renamed over it
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_file_is_read_only() {
+        let dir = temp_dir("unreadable");
+        // Reading a directory fails with something other than not found
+        let path = dir.join("directory.ron");
+        fs::create_dir_all(&path).unwrap();
+
+        let (_, read_only, problem) = load_ron::<Vec<u32>>(&path);
+        assert!(read_only && problem.is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
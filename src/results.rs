@@ -0,0 +1,388 @@
+use std::{collections::HashMap, path::Path};
+
+use chrono::{DateTime, Local};
+use egui_macroquad::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{list_edit, paths, Race};
+
+/// How one player did in a race
+#[derive(Serialize, Deserialize, Clone, Default, Hash)]
+pub struct Finish {
+    pub player: String,
+    /// Finishing position starting from 1, 0 if the player didn't finish
+    pub position: u32,
+    /// Finish time as entered, `m:ss.xx` or seconds
+    pub time: String,
+}
+
+/// Points awarded by finishing position
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PointsSystem {
+    pub name: String,
+    /// Points for first place, second place and so on, positions past the end get nothing
+    pub points: Vec<u32>,
+}
+
+impl PointsSystem {
+    pub fn points(&self, position: u32) -> u32 {
+        match position {
+            0 => 0,
+            position => self.points.get(position as usize - 1).copied().unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ResultsConfig {
+    pub points_systems: Vec<PointsSystem>,
+    /// Points system the leaderboard is counted with
+    pub selected: usize,
+}
+
+impl Default for ResultsConfig {
+    fn default() -> Self {
+        Self {
+            points_systems: vec![
+                PointsSystem {
+                    name: "F1".to_string(),
+                    points: vec![25, 18, 15, 12, 10, 8, 6, 4, 2, 1],
+                },
+                PointsSystem {
+                    name: "Linear".to_string(),
+                    points: (1..=10).rev().collect(),
+                },
+                PointsSystem {
+                    name: "Wins only".to_string(),
+                    points: vec![1],
+                },
+            ],
+            selected: 0,
+        }
+    }
+}
+
+impl ResultsConfig {
+    pub fn points_system(&self) -> Option<&PointsSystem> {
+        self.points_systems.get(self.selected)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Points system")
+            .selected_text(
+                self.points_system()
+                    .map(|system| system.name.as_str())
+                    .unwrap_or_default(),
+            )
+            .show_ui(ui, |ui| {
+                for (i, system) in self.points_systems.iter().enumerate() {
+                    ui.selectable_value(&mut self.selected, i, &system.name);
+                }
+            });
+
+        let mut remove = false;
+        if let Some(system) = self.points_systems.get_mut(self.selected) {
+            egui::Grid::new("points_system").show(ui, |ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut system.name);
+                ui.end_row();
+
+                ui.label("Points");
+                list_edit::comma_separated(
+                    ui,
+                    ("points_system", self.selected),
+                    &mut system.points,
+                    "Points for each position, separated by commas",
+                );
+                ui.end_row();
+            });
+            remove = self.points_systems.len() > 1 && ui.button("Remove points system").clicked();
+        }
+        if remove {
+            self.points_systems.remove(self.selected);
+            self.selected = 0;
+        }
+        if ui.button("Add points system").clicked() {
+            self.points_systems.push(PointsSystem {
+                name: "New points system".to_string(),
+                points: vec![1],
+            });
+            self.selected = self.points_systems.len() - 1;
+        }
+    }
+}
+
+/// Editor for the finishes of a race
+pub fn finishes_ui(ui: &mut egui::Ui, finishes: &mut Vec<Finish>, system: Option<&PointsSystem>) {
+    let mut remove = None;
+
+    egui::Grid::new("finishes").show(ui, |ui| {
+        ui.label("Player");
+        ui.label("Time");
+        ui.label("Position");
+        ui.label("Points");
+        ui.end_row();
+
+        for (i, finish) in finishes.iter_mut().enumerate() {
+            ui.text_edit_singleline(&mut finish.player);
+            ui.add(egui::TextEdit::singleline(&mut finish.time).desired_width(70.0));
+            ui.add(
+                egui::DragValue::new(&mut finish.position).custom_formatter(|position, _| {
+                    match position as u32 {
+                        0 => "DNF".to_string(),
+                        position => position.to_string(),
+                    }
+                }),
+            );
+            ui.label(
+                system
+                    .map(|system| system.points(finish.position).to_string())
+                    .unwrap_or_default(),
+            );
+            if ui.small_button("x").clicked() {
+                remove = Some(i);
+            }
+            ui.end_row();
+        }
+    });
+
+    if let Some(i) = remove {
+        finishes.remove(i);
+    }
+
+    ui.horizontal(|ui| {
+        if ui.button("Add player").clicked() {
+            finishes.push(Finish {
+                position: finishes.len() as u32 + 1,
+                ..Default::default()
+            });
+        }
+        if ui
+            .button("Positions from times")
+            .on_hover_text("Players without a valid time are marked as not finished")
+            .clicked()
+        {
+            positions_from_times(finishes);
+        }
+    });
+}
+
+/// Rank the players by their times, fastest first
+fn positions_from_times(finishes: &mut [Finish]) {
+    let mut times = finishes
+        .iter()
+        .enumerate()
+        .filter_map(|(i, finish)| Some((i, parse_time(&finish.time)?)))
+        .collect::<Vec<_>>();
+    times.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    for finish in finishes.iter_mut() {
+        finish.position = 0;
+    }
+    for (position, (i, _)) in times.into_iter().enumerate() {
+        finishes[i].position = position as u32 + 1;
+    }
+}
+
+/// Seconds in a time written as `h:mm:ss.xx`, `m:ss.xx` or `ss.xx`
+fn parse_time(time: &str) -> Option<f64> {
+    let time = time.trim();
+    if time.is_empty() {
+        return None;
+    }
+
+    time.split(':').try_fold(0.0, |total, part| {
+        let part = part.trim().parse::<f64>().ok()?;
+        (part >= 0.0).then_some(total * 60.0 + part)
+    })
+}
+
+/// Results of a race as recorded in the leaderboard
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecordedRace {
+    pub seed: u64,
+    pub date: DateTime<Local>,
+    pub finishes: Vec<Finish>,
+}
+
+/// A player's totals over all recorded races
+pub struct Standing {
+    pub player: String,
+    pub races: u32,
+    pub wins: u32,
+    pub points: u32,
+}
+
+/// Results of all recorded races, kept across sessions
+#[derive(Serialize, Deserialize, Default)]
+pub struct Leaderboard {
+    pub races: Vec<RecordedRace>,
+    /// Set when the file on disk couldn't be loaded or backed up, it isn't saved over then
+    #[serde(skip)]
+    read_only: bool,
+}
+
+impl Leaderboard {
+    /// Load the leaderboard, a missing file is an empty leaderboard. See [`paths::load_ron`] for what
+    /// happens with files that can't be loaded.
+    pub fn load(path: &Path) -> (Self, Option<String>) {
+        let (mut leaderboard, read_only, problem) = paths::load_ron::<Self>(path);
+        leaderboard.read_only = read_only;
+        (leaderboard, problem)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if self.read_only {
+            return Err(format!(
+                "Not saving the leaderboard, {} couldn't be loaded",
+                path.display()
+            ));
+        }
+
+        let data = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|why| format!("Failed to serialize leaderboard: {}", why))?;
+
+        paths::write_atomic(path, data.as_bytes())
+            .map_err(|why| format!("Failed to save leaderboard to {}: {}", path.display(), why))
+    }
+
+    /// Add the results of a race, replacing those recorded for it before
+    pub fn record(&mut self, race: &Race) {
+        let recorded = RecordedRace {
+            seed: race.seed,
+            date: race.generated_at,
+            finishes: race
+                .results
+                .iter()
+                .filter(|finish| !finish.player.trim().is_empty())
+                .cloned()
+                .collect(),
+        };
+
+        match self.races.iter_mut().find(|other| other.seed == race.seed) {
+            Some(other) => *other = recorded,
+            None => self.races.push(recorded),
+        }
+    }
+
+    /// Totals per player, best first
+    pub fn standings(&self, system: &PointsSystem) -> Vec<Standing> {
+        let mut standings: HashMap<&str, Standing> = HashMap::new();
+
+        for finish in self.races.iter().flat_map(|race| &race.finishes) {
+            let player = finish.player.trim();
+            let standing = standings.entry(player).or_insert_with(|| Standing {
+                player: player.to_string(),
+                races: 0,
+                wins: 0,
+                points: 0,
+            });
+            standing.races += 1;
+            standing.wins += (finish.position == 1) as u32;
+            standing.points += system.points(finish.position);
+        }
+
+        let mut standings = standings.into_values().collect::<Vec<_>>();
+        standings.sort_by(|a, b| {
+            b.points
+                .cmp(&a.points)
+                .then(b.wins.cmp(&a.wins))
+                .then(a.player.cmp(&b.player))
+        });
+        standings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn finish(player: &str, position: u32, time: &str) -> Finish {
+        Finish {
+            player: player.to_string(),
+            position,
+            time: time.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_time_formats() {
+        assert_eq!(parse_time("42.5"), Some(42.5));
+        assert_eq!(parse_time(" 1:02.5 "), Some(62.5));
+        assert_eq!(parse_time("1:00:00"), Some(3600.0));
+        assert_eq!(parse_time(""), None);
+        assert_eq!(parse_time("1:x"), None);
+        assert_eq!(parse_time("-5"), None);
+    }
+
+    #[test]
+    fn positions_follow_times() {
+        let mut finishes = vec![
+            finish("a", 0, "1:10"),
+            finish("b", 0, "DNF"),
+            finish("c", 3, "65"),
+        ];
+        positions_from_times(&mut finishes);
+
+        let positions = finishes.iter().map(|f| f.position).collect::<Vec<_>>();
+        assert_eq!(positions, [2, 0, 1]);
+    }
+
+    #[test]
+    fn standings_sum_points() {
+        let system = PointsSystem {
+            name: "Test".to_string(),
+            points: vec![3, 1],
+        };
+        let race = |finishes| RecordedRace {
+            seed: 0,
+            date: Local::now(),
+            finishes,
+        };
+        let leaderboard = Leaderboard {
+            races: vec![
+                race(vec![finish("a", 1, ""), finish("b", 2, "")]),
+                race(vec![finish("b", 1, ""), finish("a", 0, "")]),
+                race(vec![finish(" b ", 1, "")]),
+            ],
+            ..Default::default()
+        };
+
+        let standings = leaderboard.standings(&system);
+        assert_eq!(standings[0].player, "b");
+        assert_eq!((standings[0].points, standings[0].wins), (7, 2));
+        assert_eq!((standings[1].points, standings[1].races), (3, 2));
+    }
+
+    #[test]
+    fn broken_file_is_backed_up() {
+        let dir =
+            std::env::temp_dir().join(format!("gtav-race-gen-results-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("leaderboard.ron");
+        fs::write(&path, "not a leaderboard").unwrap();
+
+        let (leaderboard, error) = Leaderboard::load(&path);
+        assert!(error.is_some());
+        assert!(leaderboard.races.is_empty());
+
+        let backups = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("leaderboard.ron.broken-")
+            })
+            .count();
+        assert_eq!(backups, 1);
+
+        // Backed up, so saving over the broken file is fine
+        leaderboard.save(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+//! Road networks for estimating driving distances and paths.
+//!
+//! A road graph file is RON with the nodes in map pixels and the edges as pairs of node
+//! indices, driveable both ways:
+//!
+//! ```ron
+//! (
+//!     nodes: [(580.0, 352.0), (612.5, 401.0), (700.0, 420.0)],
+//!     edges: [(0, 1), (1, 2)],
+//! )
+//! ```
+
+use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fs,
+    path::Path,
+    rc::Rc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Point;
+
+/// Shortest path trees remembered before the cache is cleared, one per start node
+const TREE_CACHE_SIZE: usize = 512;
+/// Nearest nodes remembered before the cache is cleared
+const NEAREST_CACHE_SIZE: usize = 10_000;
+
+#[derive(Serialize, Deserialize)]
+struct RoadGraphFile {
+    nodes: Vec<(f32, f32)>,
+    edges: Vec<(usize, usize)>,
+}
+
+/// Shortest distances from one node to all others, with the node each is reached from
+struct Tree {
+    distances: Vec<f32>,
+    previous: Vec<Option<usize>>,
+}
+
+pub struct RoadGraph {
+    nodes: Vec<Point>,
+    /// Neighbours of every node with the length of the road to them
+    adjacency: Vec<Vec<(usize, f32)>>,
+    /// Nearest node of every point looked up so far
+    nearest: RefCell<HashMap<[u32; 2], usize>>,
+    trees: RefCell<HashMap<usize, Rc<Tree>>>,
+}
+
+impl RoadGraph {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data =
+            fs::read(path).map_err(|why| format!("Failed to read {}: {}", path.display(), why))?;
+        let file = ron::de::from_bytes::<RoadGraphFile>(&data)
+            .map_err(|why| format!("Failed to parse {}: {}", path.display(), why))?;
+
+        if file.nodes.is_empty() {
+            return Err(format!("{} has no nodes", path.display()));
+        }
+
+        let nodes = file
+            .nodes
+            .iter()
+            .map(|(x, y)| Point::new(*x, *y))
+            .collect::<Vec<_>>();
+        let mut adjacency = vec![Vec::new(); nodes.len()];
+        for (a, b) in file.edges {
+            if a >= nodes.len() || b >= nodes.len() {
+                return Err(format!(
+                    "{} has an edge between nodes {} and {}, but only {} nodes",
+                    path.display(),
+                    a,
+                    b,
+                    nodes.len()
+                ));
+            }
+            let length = distance(nodes[a], nodes[b]);
+            adjacency[a].push((b, length));
+            adjacency[b].push((a, length));
+        }
+
+        Ok(Self {
+            nodes,
+            adjacency,
+            nearest: RefCell::new(HashMap::new()),
+            trees: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Driving distance in map pixels, straight if the roads don't connect the points.
+    /// The points are joined to the roads at their nearest nodes.
+    pub fn distance(&self, from: Point, to: Point) -> f32 {
+        let start = self.nearest(from);
+        let goal = self.nearest(to);
+        let road = self.tree(start).distances[goal];
+
+        if road.is_finite() {
+            distance(from, self.nodes[start]) + road + distance(self.nodes[goal], to)
+        } else {
+            distance(from, to)
+        }
+    }
+
+    /// Estimated driving path, straight if the roads don't connect the points
+    pub fn path(&self, from: Point, to: Point) -> Vec<Point> {
+        let start = self.nearest(from);
+        let goal = self.nearest(to);
+        let tree = self.tree(start);
+
+        if !tree.distances[goal].is_finite() {
+            return vec![from, to];
+        }
+
+        let mut nodes = vec![goal];
+        while let Some(node) = tree.previous[*nodes.last().unwrap()] {
+            nodes.push(node);
+        }
+
+        let mut path = vec![from];
+        path.extend(nodes.iter().rev().map(|node| self.nodes[*node]));
+        path.push(to);
+        path
+    }
+
+    fn nearest(&self, point: Point) -> usize {
+        let mut nearest = self.nearest.borrow_mut();
+        if nearest.len() >= NEAREST_CACHE_SIZE {
+            nearest.clear();
+        }
+        *nearest
+            .entry([point.x.to_bits(), point.y.to_bits()])
+            .or_insert_with(|| {
+                self.nodes
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| distance(**a, point).total_cmp(&distance(**b, point)))
+                    .map(|(i, _)| i)
+                    .unwrap_or_default()
+            })
+    }
+
+    fn tree(&self, start: usize) -> Rc<Tree> {
+        if let Some(tree) = self.trees.borrow().get(&start) {
+            return tree.clone();
+        }
+
+        let tree = Rc::new(self.dijkstra(start));
+
+        let mut trees = self.trees.borrow_mut();
+        if trees.len() >= TREE_CACHE_SIZE {
+            trees.clear();
+        }
+        trees.insert(start, tree.clone());
+        tree
+    }
+
+    fn dijkstra(&self, start: usize) -> Tree {
+        let mut distances = vec![f32::INFINITY; self.nodes.len()];
+        let mut previous = vec![None; self.nodes.len()];
+        // Bits of non-negative floats sort like the floats themselves
+        let mut queue = BinaryHeap::new();
+
+        distances[start] = 0.0;
+        queue.push(Reverse((0f32.to_bits(), start)));
+
+        while let Some(Reverse((node_distance, node))) = queue.pop() {
+            let node_distance = f32::from_bits(node_distance);
+            if node_distance > distances[node] {
+                continue;
+            }
+            for &(next, length) in &self.adjacency[node] {
+                let next_distance = node_distance + length;
+                if next_distance < distances[next] {
+                    distances[next] = next_distance;
+                    previous[next] = Some(node);
+                    queue.push(Reverse((next_distance.to_bits(), next)));
+                }
+            }
+        }
+
+        Tree {
+            distances,
+            previous,
+        }
+    }
+}
+
+fn distance(from: Point, to: Point) -> f32 {
+    from.distance_to((to.x, to.y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, ron: &str) -> Result<RoadGraph, String> {
+        let path = std::env::temp_dir().join(format!(
+            "gtav-race-gen-roads-{}-{}.ron",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, ron).unwrap();
+        let graph = RoadGraph::load(&path);
+        fs::remove_file(&path).unwrap();
+        graph
+    }
+
+    /// Road around two sides of a square, with the fourth corner not connected
+    fn graph() -> RoadGraph {
+        load(
+            "square",
+            "(nodes: [(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)], \
+             edges: [(0, 1), (1, 2)])",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn distance_follows_the_roads() {
+        let graph = graph();
+
+        assert_eq!(
+            graph.distance(Point::new(0.0, 0.0), Point::new(100.0, 100.0)),
+            200.0
+        );
+        // Off-road ends are joined to the nearest nodes
+        assert_eq!(
+            graph.distance(Point::new(-10.0, 0.0), Point::new(100.0, 110.0)),
+            220.0
+        );
+    }
+
+    #[test]
+    fn path_goes_through_the_nodes() {
+        let graph = graph();
+        let from = Point::new(-10.0, 0.0);
+        let to = Point::new(100.0, 110.0);
+
+        assert!(
+            graph.path(from, to)
+                == [
+                    from,
+                    Point::new(0.0, 0.0),
+                    Point::new(100.0, 0.0),
+                    Point::new(100.0, 100.0),
+                    to
+                ]
+        );
+    }
+
+    #[test]
+    fn unconnected_points_are_straight() {
+        let graph = graph();
+        let from = Point::new(0.0, 0.0);
+        let to = Point::new(0.0, 90.0);
+
+        assert_eq!(graph.distance(from, to), 90.0);
+        assert!(graph.path(from, to) == [from, to]);
+    }
+
+    #[test]
+    fn bad_files_are_rejected() {
+        assert!(load("empty", "(nodes: [], edges: [])").is_err());
+        let error = load("edge", "(nodes: [(0.0, 0.0)], edges: [(0, 1)])").err();
+        assert!(error.is_some_and(|error| error.contains("only 1 nodes")));
+    }
+}
@@ -0,0 +1,263 @@
+use egui_macroquad::egui;
+use macroquad::rand::{self, ChooseRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::list_edit;
+
+/// What the players are given for each leg
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum AssignmentMode {
+    /// Everyone drives the leg's class
+    Off,
+    /// Each player gets one of the race's classes
+    Class,
+    /// Each player gets a vehicle from the leg's class
+    Vehicle,
+}
+
+impl AssignmentMode {
+    pub const ALL: &'static [AssignmentMode] = &[
+        AssignmentMode::Off,
+        AssignmentMode::Class,
+        AssignmentMode::Vehicle,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AssignmentMode::Off => "Off",
+            AssignmentMode::Class => "Class per player",
+            AssignmentMode::Vehicle => "Vehicle per player",
+        }
+    }
+}
+
+/// Vehicles that can be handed out for a class
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct VehicleList {
+    pub class: String,
+    pub vehicles: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Roster {
+    pub players: Vec<String>,
+    pub vehicles: Vec<VehicleList>,
+    pub mode: AssignmentMode,
+}
+
+impl Default for Roster {
+    fn default() -> Self {
+        Self {
+            players: Vec::new(),
+            vehicles: Vec::new(),
+            mode: AssignmentMode::Off,
+        }
+    }
+}
+
+impl Roster {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Assign to players")
+            .selected_text(self.mode.name())
+            .show_ui(ui, |ui| {
+                for mode in AssignmentMode::ALL {
+                    ui.selectable_value(&mut self.mode, *mode, mode.name());
+                }
+            });
+
+        ui.label("Players");
+        let mut remove = None;
+        for (i, player) in self.players.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(player);
+                if ui.small_button("x").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            self.players.remove(i);
+        }
+        if ui.button("Add player").clicked() {
+            self.players.push(String::new());
+        }
+
+        ui.separator();
+        ui.label("Vehicles by class");
+        let mut remove = None;
+        egui::Grid::new("roster_vehicles").show(ui, |ui| {
+            for (i, list) in self.vehicles.iter_mut().enumerate() {
+                ui.add(
+                    egui::TextEdit::singleline(&mut list.class)
+                        .hint_text("Class")
+                        .desired_width(100.0),
+                );
+                list_edit::comma_separated(
+                    ui,
+                    ("roster_vehicles", i),
+                    &mut list.vehicles,
+                    "Vehicles separated by commas",
+                );
+                if ui.small_button("x").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            self.vehicles.remove(i);
+        }
+        if ui.button("Add class").clicked() {
+            self.vehicles.push(VehicleList::default());
+        }
+    }
+
+    /// What each player gets on each leg, empty if nothing is assigned
+    pub fn assign(&self, classes: &[String]) -> Vec<(String, Vec<String>)> {
+        let players = self
+            .players
+            .iter()
+            .filter(|player| !player.trim().is_empty())
+            .collect::<Vec<_>>();
+        if players.is_empty() {
+            return Vec::new();
+        }
+
+        // One shuffled list per leg, players take turns through it
+        let legs: Vec<Vec<String>> = match self.mode {
+            AssignmentMode::Off => return Vec::new(),
+            AssignmentMode::Class => {
+                let mut pool = classes
+                    .iter()
+                    .filter(|class| !class.is_empty())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                pool.sort();
+                pool.dedup();
+                pool.shuffle();
+                // Rotating by leg gives every player each class about as often
+                (0..classes.len())
+                    .map(|leg| {
+                        let mut pool = pool.clone();
+                        if !pool.is_empty() {
+                            let len = pool.len();
+                            pool.rotate_left(leg % len);
+                        }
+                        pool
+                    })
+                    .collect()
+            }
+            AssignmentMode::Vehicle => classes
+                .iter()
+                .map(|class| {
+                    let mut vehicles = self
+                        .vehicles
+                        .iter()
+                        .find(|list| list.class.trim().eq_ignore_ascii_case(class.trim()))
+                        .map(|list| list.vehicles.clone())
+                        .unwrap_or_default();
+                    vehicles.shuffle();
+                    vehicles
+                })
+                .collect(),
+        };
+
+        // Who gets the first pick changes from race to race
+        let offset = rand::gen_range(0, players.len());
+
+        players
+            .iter()
+            .enumerate()
+            .map(|(i, player)| {
+                let assigned = legs
+                    .iter()
+                    .zip(classes)
+                    .map(|(options, class)| match options.len() {
+                        // Nothing to hand out, everyone drives the leg's class
+                        0 => class.clone(),
+                        len => options[(i + offset) % len].clone(),
+                    })
+                    .collect();
+                (player.to_string(), assigned)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    fn roster(players: &[&str], mode: AssignmentMode) -> Roster {
+        Roster {
+            players: strings(players),
+            vehicles: vec![VehicleList {
+                class: "super".to_string(),
+                vehicles: strings(&["Adder", "Zentorno"]),
+            }],
+            mode,
+        }
+    }
+
+    #[test]
+    fn nothing_assigned_without_players_or_mode() {
+        let classes = strings(&["Super", "Sports"]);
+
+        assert!(roster(&["Aino"], AssignmentMode::Off)
+            .assign(&classes)
+            .is_empty());
+        assert!(roster(&["", " "], AssignmentMode::Class)
+            .assign(&classes)
+            .is_empty());
+    }
+
+    #[test]
+    fn classes_rotate_between_players() {
+        rand::srand(1);
+        let classes = strings(&["Super", "Sports", "Muscle"]);
+        let assignments =
+            roster(&["Aino", "Eero", "Ilona"], AssignmentMode::Class).assign(&classes);
+
+        assert_eq!(assignments.len(), 3);
+        for leg in 0..classes.len() {
+            // Nobody shares a class on a leg
+            let mut on_leg = assignments
+                .iter()
+                .map(|(_, assigned)| assigned[leg].clone())
+                .collect::<Vec<_>>();
+            on_leg.sort();
+            on_leg.dedup();
+            assert_eq!(on_leg.len(), 3);
+        }
+        for (_, assigned) in &assignments {
+            // Everyone drives every class once
+            let mut assigned = assigned.clone();
+            assigned.sort();
+            assert_eq!(assigned, strings(&["Muscle", "Sports", "Super"]));
+        }
+    }
+
+    #[test]
+    fn vehicles_fall_back_to_the_class() {
+        rand::srand(1);
+        let classes = strings(&["Super", "Boats"]);
+        let assignments = roster(&["Aino", "Eero"], AssignmentMode::Vehicle).assign(&classes);
+
+        let mut supers = assignments
+            .iter()
+            .map(|(_, assigned)| assigned[0].clone())
+            .collect::<Vec<_>>();
+        supers.sort();
+        // Matched regardless of case, and both vehicles handed out
+        assert_eq!(supers, strings(&["Adder", "Zentorno"]));
+        // No list for the class
+        assert!(assignments
+            .iter()
+            .all(|(_, assigned)| assigned[1] == "Boats"));
+    }
+}
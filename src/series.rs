@@ -0,0 +1,206 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{paths, Point, Race};
+
+/// Races generated together for a race night, no checkpoint is used twice
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Series {
+    pub name: String,
+    /// Name of the map the races were made on, their points are in its pixel space
+    #[serde(default)]
+    pub map: String,
+    pub races: Vec<Race>,
+    /// Race shown in the race view
+    pub current: usize,
+}
+
+impl Series {
+    pub fn new(races: Vec<Race>, map: &str) -> Self {
+        Self {
+            name: format!("series-{}", chrono::Local::now().format("%Y-%m-%d-%H%M")),
+            map: map.to_string(),
+            races,
+            current: 0,
+        }
+    }
+
+    pub fn load(name: &str) -> Result<Self, String> {
+        load(&paths::series_dir(), name)
+    }
+
+    pub fn save(&self) -> Result<PathBuf, String> {
+        save(self, &paths::series_dir())
+    }
+
+    /// Names of the saved series, newest first with the default names
+    pub fn saved() -> Vec<String> {
+        let mut names = fs::read_dir(paths::series_dir())
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| {
+                        entry
+                            .file_name()
+                            .to_str()?
+                            .strip_suffix(".ron")
+                            .map(|name| name.to_string())
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names.reverse();
+        names
+    }
+}
+
+/// Classes for the legs of the `race`th race of a series.
+///
+/// Each race shifts the classes by one leg, so over as many races as there are legs every class
+/// is driven on every leg once.
+pub fn rotated_classes(classes: &[String], race: usize) -> Vec<String> {
+    let mut classes = classes.to_vec();
+    if !classes.is_empty() {
+        let len = classes.len();
+        classes.rotate_left(race % len);
+    }
+    classes
+}
+
+/// Every checkpoint and tryhisuojaus of the races, none of them are used again in a series
+pub fn points(races: &[Race]) -> Vec<Point> {
+    races
+        .iter()
+        .flat_map(|race| {
+            race.checkpoints
+                .iter()
+                .map(|(point, _)| *point)
+                .chain(race.tryhisuojaus.iter().map(|(_, point)| *point))
+        })
+        .collect()
+}
+
+fn load(dir: &Path, name: &str) -> Result<Series, String> {
+    let path = path(dir, name)?;
+    let data =
+        fs::read(&path).map_err(|why| format!("Failed to read {}: {}", path.display(), why))?;
+    ron::de::from_bytes(&data).map_err(|why| format!("Failed to parse {}: {}", path.display(), why))
+}
+
+fn save(series: &Series, dir: &Path) -> Result<PathBuf, String> {
+    let path = path(dir, &series.name)?;
+    let data = ron::ser::to_string_pretty(series, ron::ser::PrettyConfig::default())
+        .map_err(|why| format!("Failed to serialize series: {}", why))?;
+
+    paths::write_atomic(&path, data.as_bytes())
+        .map_err(|why| format!("Failed to save series to {}: {}", path.display(), why))?;
+    Ok(path)
+}
+
+fn path(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    if !paths::valid_name(name) {
+        return Err(format!(
+            "Invalid series name {:?}, it can't be empty or contain /, \\ or ..",
+            name
+        ));
+    }
+    Ok(dir.join(format!("{}.ron", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::rand;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn every_class_is_driven_on_every_leg() {
+        let classes = strings(&["Super", "Sports", "Muscle", "Off-road"]);
+        let races = (0..classes.len())
+            .map(|race| rotated_classes(&classes, race))
+            .collect::<Vec<_>>();
+
+        for leg in 0..classes.len() {
+            let mut on_leg = races
+                .iter()
+                .map(|race| race[leg].clone())
+                .collect::<Vec<_>>();
+            on_leg.sort();
+            let mut all = classes.clone();
+            all.sort();
+            assert_eq!(on_leg, all);
+        }
+        assert!(rotated_classes(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn points_are_not_repeated() {
+        rand::srand(1);
+        let pool = (0..12)
+            .map(|i| Point::new(i as f32, 0.0))
+            .collect::<Vec<_>>();
+        let classes = strings(&["Super", "Sports"]);
+
+        // Picked the way races of a series are, from what the earlier races left
+        let mut races = Vec::new();
+        for i in 0..3 {
+            let used = points(&races);
+            let left = pool
+                .iter()
+                .filter(|point| !used.contains(point))
+                .copied()
+                .collect::<Vec<_>>();
+            races.push(Race::new(&left[..3], &left[3..4], &classes, i));
+        }
+
+        let used = points(&races);
+        assert_eq!(used.len(), 12);
+        assert!(pool.iter().all(|point| used.contains(point)));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("gtav-race-gen-series-{}", std::process::id()));
+        let classes = strings(&["Super"]);
+        let race = Race::new(
+            &[Point::new(1.0, 2.0), Point::new(3.0, 4.0)],
+            &[],
+            &classes,
+            42,
+        );
+        let mut series = Series::new(vec![race.clone(), race], "Roads");
+        series.name = "league night ä".to_string();
+        series.current = 1;
+
+        let path = save(&series, &dir).unwrap();
+        let loaded = load(&dir, &series.name).unwrap();
+        assert_eq!(loaded.map, "Roads");
+        assert_eq!(loaded.current, 1);
+        assert_eq!(loaded.races.len(), 2);
+        assert!(loaded.races[1].checkpoints == series.races[1].checkpoints);
+        assert_eq!(loaded.races[1].seed, 42);
+
+        fs::remove_file(path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names_are_checked() {
+        let dir = Path::new("series");
+        for name in ["", "  ", "../escape", "a/b", "a\\b"] {
+            assert!(path(dir, name).is_err(), "{:?} was accepted", name);
+        }
+        assert_eq!(
+            path(dir, "league").unwrap(),
+            PathBuf::from("series/league.ron")
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{paths, series::Series, Race, State};
+
+/// What was on screen when the app was last running, restored on the next launch
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub state: State,
+    pub race: Option<Race>,
+    #[serde(default)]
+    pub series: Option<Series>,
+    /// Map the race was made on, the race is dropped if another one is selected by now
+    pub map: usize,
+    pub window_size: (f32, f32),
+}
+
+impl Session {
+    /// A missing or unreadable session just means starting fresh
+    pub fn load(path: &Path) -> Option<Self> {
+        ron::de::from_bytes(&fs::read(path).ok()?).ok()
+    }
+
+    /// What to show again if the session still fits the config, nothing if it was made on
+    /// another map
+    pub fn restore(
+        session: Option<Self>,
+        selected_map: usize,
+    ) -> (State, Option<Race>, Option<Series>) {
+        match session {
+            Some(session) if session.map == selected_map => {
+                (session.state, session.race, session.series)
+            }
+            // Anything from the session would be placed on the wrong map
+            _ => (State::Idle, None, None),
+        }
+    }
+
+    /// Write the session unless it serializes the same as `previous`, which is updated on write
+    pub fn save(&self, path: &Path, previous: &mut String) -> Result<(), String> {
+        let data = ron::ser::to_string(self)
+            .map_err(|why| format!("Failed to serialize session: {}", why))?;
+
+        if data == *previous {
+            return Ok(());
+        }
+
+        paths::write_atomic(path, data.as_bytes())
+            .map_err(|why| format!("Failed to save session to {}: {}", path.display(), why))?;
+        *previous = data;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point;
+
+    fn session(map: usize) -> Session {
+        let points = [
+            Point::new(0.0, 0.0),
+            Point::new(100.0, 0.0),
+            Point::new(100.0, 100.0),
+        ];
+        let classes = ["Sports".to_string(), "Super".to_string()];
+        Session {
+            state: State::Config(vec![(Point::new(1.0, 2.0), "Pier".to_string())]),
+            race: Some(Race::new(&points, &[], &classes, 7)),
+            series: None,
+            map,
+            window_size: (800.0, 600.0),
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir =
+            std::env::temp_dir().join(format!("gtav-race-gen-session-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.session.ron");
+
+        let saved = session(1);
+        let mut previous = String::new();
+        saved.save(&path, &mut previous).unwrap();
+        let loaded = Session::load(&path).unwrap();
+        assert_eq!(
+            ron::ser::to_string(&loaded).unwrap(),
+            ron::ser::to_string(&saved).unwrap()
+        );
+
+        // Nothing is written when nothing changed
+        fs::remove_file(&path).unwrap();
+        saved.save(&path, &mut previous).unwrap();
+        assert!(!path.exists());
+
+        fs::write(&path, "not a session").unwrap();
+        assert!(Session::load(&path).is_none());
+        assert!(Session::load(&dir.join("missing.session.ron")).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restored_on_the_same_map() {
+        let (state, race, series) = Session::restore(Some(session(1)), 1);
+        assert!(matches!(state, State::Config(points) if points.len() == 1));
+        assert_eq!(race.unwrap().seed, 7);
+        assert!(series.is_none());
+    }
+
+    #[test]
+    fn other_maps_start_fresh() {
+        // Saved on another map that's still there
+        let (state, race, series) = Session::restore(Some(session(1)), 0);
+        assert!(matches!(state, State::Idle));
+        assert!(race.is_none() && series.is_none());
+
+        // Saved on a map that has been removed since, the selection was clamped on load
+        let (state, race, series) = Session::restore(Some(session(5)), 2);
+        assert!(matches!(state, State::Idle));
+        assert!(race.is_none() && series.is_none());
+
+        let (state, race, _) = Session::restore(None, 0);
+        assert!(matches!(state, State::Idle));
+        assert!(race.is_none());
+    }
+}
@@ -0,0 +1,339 @@
+//! Compact share codes for races.
+//!
+//! A code is URL-safe base64 of the following, with all integers little endian:
+//!
+//! - version byte
+//! - flags byte, bit 0 set if points are indices into the pool instead of coordinates
+//! - pool fingerprint (u32, CRC32 of the pool) if points are pool indices
+//! - seed (LEB128)
+//! - checkpoint count (u8), then for each checkpoint its point and, except for the goal, its class
+//! - tryhisuojaus count (u8), then for each the leg index (u8) and its point
+//! - CRC32 of everything before it (u32)
+//!
+//! Points are either a u16 pool index or two u16 pixel coordinates, classes are a length byte
+//! followed by UTF-8.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Local;
+
+use crate::{Point, Race};
+
+const VERSION: u8 = 1;
+const FLAG_POOL_INDICES: u8 = 1;
+
+pub fn encode(race: &Race, pool: &[Point]) -> String {
+    let indices = race
+        .checkpoints
+        .iter()
+        .map(|(point, _)| point)
+        .chain(race.tryhisuojaus.iter().map(|(_, point)| point))
+        .map(|point| pool.iter().position(|pool_point| pool_point == point))
+        .collect::<Option<Vec<_>>>()
+        .filter(|indices| indices.iter().all(|i| *i <= u16::MAX as usize));
+
+    let mut data = vec![VERSION];
+
+    let mut indices = match indices {
+        Some(indices) => {
+            data.push(FLAG_POOL_INDICES);
+            data.extend(pool_fingerprint(pool).to_le_bytes());
+            Some(indices.into_iter())
+        }
+        None => {
+            data.push(0);
+            None
+        }
+    };
+
+    let mut write_point = |data: &mut Vec<u8>, point: &Point| match &mut indices {
+        Some(indices) => data.extend((indices.next().unwrap() as u16).to_le_bytes()),
+        None => {
+            data.extend((point.x.round().clamp(0.0, u16::MAX as f32) as u16).to_le_bytes());
+            data.extend((point.y.round().clamp(0.0, u16::MAX as f32) as u16).to_le_bytes());
+        }
+    };
+
+    write_varint(&mut data, race.seed);
+
+    let n_checkpoints = race.checkpoints.len().min(u8::MAX as usize);
+    data.push(n_checkpoints as u8);
+    for (i, (point, class)) in race.checkpoints[..n_checkpoints].iter().enumerate() {
+        write_point(&mut data, point);
+        if i + 1 < n_checkpoints {
+            let class = class.as_deref().unwrap_or_default();
+            // Cut long names between characters so they stay valid UTF-8
+            let mut len = class.len().min(u8::MAX as usize);
+            while !class.is_char_boundary(len) {
+                len -= 1;
+            }
+            data.push(len as u8);
+            data.extend(&class.as_bytes()[..len]);
+        }
+    }
+
+    data.push(race.tryhisuojaus.len().min(u8::MAX as usize) as u8);
+    for (index, point) in race.tryhisuojaus.iter().take(u8::MAX as usize) {
+        data.push((*index).min(u8::MAX as usize) as u8);
+        write_point(&mut data, point);
+    }
+
+    data.extend(crc32fast::hash(&data).to_le_bytes());
+
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+pub fn decode(code: &str, pool: &[Point]) -> Result<Race, String> {
+    let data = URL_SAFE_NO_PAD
+        .decode(code.trim())
+        .map_err(|_| "Share code is not valid base64".to_string())?;
+
+    if data.len() < 4 {
+        return Err("Share code is too short".to_string());
+    }
+    let (data, checksum) = data.split_at(data.len() - 4);
+    if crc32fast::hash(data).to_le_bytes() != checksum {
+        return Err("Share code is damaged, check that it was copied completely".to_string());
+    }
+
+    let mut reader = Reader(data);
+
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(format!("Unsupported share code version {}", version));
+    }
+
+    let uses_pool = reader.u8()? & FLAG_POOL_INDICES != 0;
+    if uses_pool && reader.u32()? != pool_fingerprint(pool) {
+        return Err(
+            "This share code was made with a different checkpoint pool than the current map's"
+                .to_string(),
+        );
+    }
+
+    let read_point = |reader: &mut Reader| -> Result<Point, String> {
+        if uses_pool {
+            let index = reader.u16()? as usize;
+            pool.get(index)
+                .copied()
+                .ok_or_else(|| format!("Checkpoint {} is not in the pool", index + 1))
+        } else {
+            Ok(Point::new(reader.u16()? as f32, reader.u16()? as f32))
+        }
+    };
+
+    let seed = reader.varint()?;
+
+    let n_checkpoints = reader.u8()? as usize;
+    if n_checkpoints < 2 {
+        return Err("Share code has less than two checkpoints".to_string());
+    }
+    let mut checkpoints = Vec::with_capacity(n_checkpoints);
+    for i in 0..n_checkpoints {
+        let point = read_point(&mut reader)?;
+        let class = if i + 1 < n_checkpoints {
+            let len = reader.u8()? as usize;
+            let class = String::from_utf8(reader.bytes(len)?.to_vec())
+                .map_err(|_| "Share code has an invalid class name".to_string())?;
+            Some(class)
+        } else {
+            None
+        };
+        checkpoints.push((point, class));
+    }
+
+    let n_tryhisuojaus = reader.u8()? as usize;
+    let mut tryhisuojaus = Vec::with_capacity(n_tryhisuojaus);
+    for _ in 0..n_tryhisuojaus {
+        let index = reader.u8()? as usize;
+        if index + 1 >= n_checkpoints {
+            return Err("Share code has a tryhisuojaus outside of the race".to_string());
+        }
+        // Races keep them sorted by leg, the route is drawn through them in that order
+        if tryhisuojaus.last().is_some_and(|(last, _)| *last > index) {
+            return Err("Share code has its tryhisuojaus out of order".to_string());
+        }
+        tryhisuojaus.push((index, read_point(&mut reader)?));
+    }
+
+    if !reader.0.is_empty() {
+        return Err("Share code has trailing data".to_string());
+    }
+
+    Ok(Race {
+        checkpoints,
+        tryhisuojaus,
+        seed,
+        generated_at: Local::now(),
+        results: Vec::new(),
+        stopwatch: Default::default(),
+        assignments: Vec::new(),
+    })
+}
+
+/// Identifies a pool, so codes referring to pool indices aren't opened with a different pool
+fn pool_fingerprint(pool: &[Point]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for point in pool {
+        hasher.update(&point.x.to_le_bytes());
+        hasher.update(&point.y.to_le_bytes());
+    }
+    hasher.finalize()
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            data.push(byte);
+            return;
+        }
+        data.push(byte | 0x80);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("Share code is truncated".to_string());
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Share code has an invalid seed".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> Vec<Point> {
+        (0..10)
+            .map(|i| Point::new(100.0 * i as f32, 50.0 * i as f32))
+            .collect()
+    }
+
+    fn race(points: &[Point], classes: &[&str], tryhisuojaus: Vec<(usize, Point)>) -> Race {
+        let mut checkpoints = points
+            .iter()
+            .zip(classes)
+            .map(|(point, class)| (*point, Some(class.to_string())))
+            .collect::<Vec<_>>();
+        checkpoints.push((*points.last().unwrap(), None));
+
+        Race {
+            checkpoints,
+            tryhisuojaus,
+            seed: 0xdead_beef_1234,
+            generated_at: Local::now(),
+            results: Vec::new(),
+            stopwatch: Default::default(),
+            assignments: Vec::new(),
+        }
+    }
+
+    fn assert_same(a: &Race, b: &Race) {
+        assert!(a.checkpoints == b.checkpoints);
+        assert!(a.tryhisuojaus == b.tryhisuojaus);
+        assert_eq!(a.seed, b.seed);
+    }
+
+    #[test]
+    fn round_trip_with_pool_indices() {
+        let pool = pool();
+        let race = race(
+            &[pool[3], pool[7], pool[1]],
+            &["Super", "Motorcycles"],
+            vec![(0, pool[5]), (1, pool[9])],
+        );
+
+        let code = encode(&race, &pool);
+        assert_same(&decode(&code, &pool).unwrap(), &race);
+    }
+
+    #[test]
+    fn round_trip_with_coordinates_and_unicode() {
+        let pool = pool();
+        // Not in the pool, so the points are written as coordinates
+        let race = race(
+            &[Point::new(12.0, 34.0), pool[2], Point::new(4000.0, 6000.0)],
+            &["Lentokoneet ✈", "Ääliöauto"],
+            vec![(1, Point::new(500.0, 501.0))],
+        );
+
+        let code = encode(&race, &pool);
+        assert_same(&decode(&code, &pool).unwrap(), &race);
+        // Coordinates don't depend on the pool
+        assert_same(&decode(&code, &[]).unwrap(), &race);
+    }
+
+    #[test]
+    fn long_class_is_cut_between_characters() {
+        let pool = pool();
+        let class = "ä".repeat(200);
+        let race = race(&[pool[0], pool[1]], &[&class], Vec::new());
+
+        let decoded = decode(&encode(&race, &pool), &pool).unwrap();
+        let decoded_class = decoded.checkpoints[0].1.as_deref().unwrap();
+        assert_eq!(decoded_class, "ä".repeat(127));
+    }
+
+    #[test]
+    fn other_pool_is_rejected() {
+        let pool = pool();
+        let race = race(&[pool[0], pool[1]], &["Super"], Vec::new());
+        let code = encode(&race, &pool);
+
+        assert!(decode(&code, &pool[1..]).is_err());
+    }
+
+    #[test]
+    fn damaged_code_is_rejected() {
+        let pool = pool();
+        let race = race(&[pool[0], pool[1]], &["Super"], Vec::new());
+        let mut code = encode(&race, &pool).into_bytes();
+        code[5] = if code[5] == b'A' { b'B' } else { b'A' };
+
+        let error = decode(&String::from_utf8(code).unwrap(), &pool).err();
+        assert!(error.is_some_and(|error| error.contains("damaged")));
+        assert!(decode("not a code!", &pool).is_err());
+    }
+
+    #[test]
+    fn bad_tryhisuojaus_are_rejected() {
+        let pool = pool();
+        let points = [pool[0], pool[1], pool[2]];
+
+        let outside = race(&points, &["A", "B"], vec![(2, pool[3])]);
+        assert!(decode(&encode(&outside, &pool), &pool).is_err());
+
+        let unordered = race(&points, &["A", "B"], vec![(1, pool[3]), (0, pool[4])]);
+        assert!(decode(&encode(&unordered, &pool), &pool).is_err());
+    }
+}
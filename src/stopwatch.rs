@@ -0,0 +1,337 @@
+use std::hash::{Hash, Hasher};
+
+use chrono::{DateTime, Local};
+use egui_macroquad::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::results::Finish;
+
+/// Times a player reached the checkpoints after the start
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PlayerSplits {
+    pub player: String,
+    /// Seconds from the start to reaching checkpoint 2, 3 and so on
+    pub splits: Vec<f64>,
+}
+
+impl Hash for PlayerSplits {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.player.hash(state);
+        for split in &self.splits {
+            split.to_bits().hash(state);
+        }
+    }
+}
+
+/// Race director's timer, saved with the race so the splits can be compared later
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Stopwatch {
+    pub started_at: Option<DateTime<Local>>,
+    pub stopped_at: Option<DateTime<Local>>,
+    pub players: Vec<PlayerSplits>,
+    /// Player that clicking a checkpoint on the map records a split for
+    #[serde(skip)]
+    pub map_player: usize,
+}
+
+// Which player is selected isn't part of the race
+impl Hash for Stopwatch {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.started_at.hash(state);
+        self.stopped_at.hash(state);
+        self.players.hash(state);
+    }
+}
+
+impl Stopwatch {
+    /// Seconds since the start, up to the stop if stopped
+    pub fn elapsed(&self) -> Option<f64> {
+        let started_at = self.started_at?;
+        let until = self.stopped_at.unwrap_or_else(Local::now);
+        Some((until - started_at).num_milliseconds() as f64 / 1000.0)
+    }
+
+    pub fn running(&self) -> bool {
+        self.started_at.is_some() && self.stopped_at.is_none()
+    }
+
+    /// Record the map player reaching `checkpoint`, 0 being the start, of a race with `legs`
+    pub fn reach(&mut self, checkpoint: usize, legs: usize) -> Result<(), String> {
+        let elapsed = self.elapsed();
+        let Some(player) = self.players.get_mut(self.map_player) else {
+            return Err("Add a player to the stopwatch first".to_string());
+        };
+
+        let next = player.splits.len() + 1;
+        if next > legs {
+            Err(format!("{} has already finished", player.player))
+        } else if checkpoint != next {
+            Err(format!(
+                "{} is headed to checkpoint {}, not {}",
+                player.player,
+                next + 1,
+                checkpoint + 1
+            ))
+        } else {
+            player.splits.extend(elapsed);
+            Ok(())
+        }
+    }
+
+    /// Fastest time any player reached a checkpoint with
+    fn best_split(&self, checkpoint: usize) -> Option<f64> {
+        self.players
+            .iter()
+            .filter_map(|player| player.splits.get(checkpoint).copied())
+            .min_by(f64::total_cmp)
+    }
+
+    /// Time each player who finished spent on a leg
+    fn leg_times(&self, leg: usize) -> Vec<f64> {
+        self.players
+            .iter()
+            .filter_map(|player| {
+                let end = *player.splits.get(leg)?;
+                let start = match leg {
+                    0 => 0.0,
+                    leg => player.splits[leg - 1],
+                };
+                Some(end - start)
+            })
+            .collect()
+    }
+
+    /// Finishes for the results, ranked by who reached the goal first
+    pub fn finishes(&self, legs: usize) -> Vec<Finish> {
+        let mut finishes = self
+            .players
+            .iter()
+            .map(|player| {
+                let time = player.splits.get(legs.saturating_sub(1)).copied();
+                (player, time.filter(|_| player.splits.len() >= legs))
+            })
+            .collect::<Vec<_>>();
+        finishes.sort_by(|(_, a), (_, b)| match (a, b) {
+            (Some(a), Some(b)) => a.total_cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        });
+
+        let mut position = 0;
+        finishes
+            .into_iter()
+            .map(|(player, time)| Finish {
+                player: player.player.clone(),
+                position: match time {
+                    Some(_) => {
+                        position += 1;
+                        position
+                    }
+                    None => 0,
+                },
+                time: time.map(format_time).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Timer controls, split table and leg summary.
+    ///
+    /// `classes` has the class of every leg. Returns true if the times should be copied to the
+    /// results.
+    pub fn ui(&mut self, ui: &mut egui::Ui, classes: &[String]) -> bool {
+        let legs = classes.len();
+
+        ui.horizontal(|ui| {
+            if self.running() {
+                if ui.button("Stop").clicked() {
+                    self.stopped_at = Some(Local::now());
+                }
+            } else if ui
+                .button(match self.started_at {
+                    Some(_) => "Restart",
+                    None => "Start",
+                })
+                .on_hover_text("Starting clears the splits of the last run")
+                .clicked()
+            {
+                *self = Self {
+                    started_at: Some(Local::now()),
+                    stopped_at: None,
+                    map_player: self.map_player,
+                    players: self
+                        .players
+                        .iter()
+                        .map(|player| PlayerSplits {
+                            player: player.player.clone(),
+                            splits: Vec::new(),
+                        })
+                        .collect(),
+                };
+            }
+            if let Some(elapsed) = self.elapsed() {
+                ui.heading(format_time(elapsed));
+            }
+        });
+
+        let running = self.running();
+        let elapsed = self.elapsed();
+        let best_splits = (0..legs)
+            .map(|checkpoint| self.best_split(checkpoint))
+            .collect::<Vec<_>>();
+        let mut remove = None;
+
+        egui::Grid::new("splits").striped(true).show(ui, |ui| {
+            ui.label("Player");
+            for checkpoint in 0..legs {
+                ui.label(if checkpoint + 1 == legs {
+                    "Goal".to_string()
+                } else {
+                    (checkpoint + 2).to_string()
+                });
+            }
+            ui.end_row();
+
+            for (i, player) in self.players.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.small_button("x").clicked() {
+                        remove = Some(i);
+                    }
+                    ui.radio_value(&mut self.map_player, i, "").on_hover_text(
+                        "Clicking a checkpoint on the map records a split for this player",
+                    );
+                    ui.add(egui::TextEdit::singleline(&mut player.player).desired_width(100.0));
+                });
+
+                for (checkpoint, best_split) in best_splits.iter().enumerate() {
+                    match player.splits.get(checkpoint) {
+                        Some(split) => {
+                            let delta = split - best_split.unwrap_or(*split);
+                            ui.label(if delta > 0.0 {
+                                format!("{} (+{:.1})", format_time(*split), delta)
+                            } else {
+                                format_time(*split)
+                            });
+                        }
+                        None if running && checkpoint == player.splits.len() => {
+                            let text = if checkpoint + 1 == legs {
+                                "Finish"
+                            } else {
+                                "Split"
+                            };
+                            if ui.button(text).clicked() {
+                                player.splits.extend(elapsed);
+                            }
+                        }
+                        None => {
+                            ui.label("");
+                        }
+                    }
+                }
+                ui.end_row();
+            }
+        });
+
+        if let Some(i) = remove {
+            self.players.remove(i);
+            if self.map_player > i {
+                self.map_player -= 1;
+            }
+        }
+        if ui.button("Add player").clicked() {
+            self.players.push(PlayerSplits::default());
+        }
+
+        ui.separator();
+
+        let averages = (0..legs)
+            .map(|leg| {
+                let times = self.leg_times(leg);
+                (!times.is_empty()).then(|| times.iter().sum::<f64>() / times.len() as f64)
+            })
+            .collect::<Vec<_>>();
+        let slowest = averages
+            .iter()
+            .enumerate()
+            .filter_map(|(leg, average)| Some((leg, (*average)?)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(leg, _)| leg);
+
+        egui::Grid::new("leg_times").striped(true).show(ui, |ui| {
+            ui.label("Leg");
+            ui.label("Class");
+            ui.label("Best");
+            ui.label("Average");
+            ui.end_row();
+
+            for (leg, class) in classes.iter().enumerate() {
+                ui.label(format!("{} - {}", leg + 1, leg + 2));
+                ui.label(class);
+                ui.label(
+                    self.leg_times(leg)
+                        .into_iter()
+                        .min_by(f64::total_cmp)
+                        .map(format_time)
+                        .unwrap_or_default(),
+                );
+                let average = averages[leg].map(format_time).unwrap_or_default();
+                if slowest == Some(leg) && legs > 1 {
+                    ui.colored_label(egui::Color32::RED, format!("{} (slowest)", average));
+                } else {
+                    ui.label(average);
+                }
+                ui.end_row();
+            }
+        });
+
+        ui.add_enabled(
+            self.players.iter().any(|player| !player.splits.is_empty()),
+            egui::Button::new("Copy times to results"),
+        )
+        .clicked()
+    }
+}
+
+/// `m:ss.s`, readable back by the results
+pub fn format_time(seconds: f64) -> String {
+    let tenths = (seconds.max(0.0) * 10.0).round() as u64;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running(players: &[&str]) -> Stopwatch {
+        Stopwatch {
+            started_at: Some(Local::now()),
+            players: players
+                .iter()
+                .map(|player| PlayerSplits {
+                    player: player.to_string(),
+                    splits: Vec::new(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn checkpoints_are_reached_in_order() {
+        let mut stopwatch = running(&["Aino", "Eero"]);
+        stopwatch.map_player = 1;
+
+        assert!(stopwatch.reach(2, 2).is_err());
+        assert!(stopwatch.reach(1, 2).is_ok());
+        assert!(stopwatch.reach(2, 2).is_ok());
+        assert!(stopwatch.reach(2, 2).is_err());
+
+        assert!(stopwatch.players[0].splits.is_empty());
+        assert_eq!(stopwatch.players[1].splits.len(), 2);
+        assert_eq!(stopwatch.finishes(2)[0].player, "Eero");
+    }
+
+    #[test]
+    fn needs_a_player() {
+        let mut stopwatch = running(&[]);
+        assert!(stopwatch.reach(1, 2).is_err());
+    }
+}
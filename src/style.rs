@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use egui_macroquad::egui;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// RGBA color that can be stored in the config
+pub type Rgba = [u8; 4];
+
+pub fn color(rgba: Rgba) -> Color {
+    Color::from_rgba(rgba[0], rgba[1], rgba[2], rgba[3])
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum FontChoice {
+    VisiaPro,
+    SourceCodePro,
+    SourceCodeProLight,
+    SourceCodeProBlack,
+    Hack,
+    HackBold,
+}
+
+impl FontChoice {
+    pub const ALL: &'static [FontChoice] = &[
+        FontChoice::VisiaPro,
+        FontChoice::SourceCodePro,
+        FontChoice::SourceCodeProLight,
+        FontChoice::SourceCodeProBlack,
+        FontChoice::Hack,
+        FontChoice::HackBold,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FontChoice::VisiaPro => "Visia Pro",
+            FontChoice::SourceCodePro => "Source Code Pro",
+            FontChoice::SourceCodeProLight => "Source Code Pro Light",
+            FontChoice::SourceCodeProBlack => "Source Code Pro Black",
+            FontChoice::Hack => "Hack",
+            FontChoice::HackBold => "Hack Bold",
+        }
+    }
+
+    fn load(self) -> StyleFont {
+        let fill: &[u8] = match self {
+            FontChoice::VisiaPro => include_bytes!("../res/VisiaPro-Bold.ttf"),
+            FontChoice::SourceCodePro => include_bytes!("../res/SourceCodePro-Regular.ttf"),
+            FontChoice::SourceCodeProLight => include_bytes!("../res/SourceCodePro-Light.ttf"),
+            FontChoice::SourceCodeProBlack => include_bytes!("../res/SourceCodePro-Black.ttf"),
+            FontChoice::Hack => include_bytes!("../res/Hack-Regular.ttf"),
+            FontChoice::HackBold => include_bytes!("../res/Hack-Bold.ttf"),
+        };
+        // Only Visia Pro ships with a dedicated outline font, the rest get a drawn outline
+        let outline: Option<&[u8]> = match self {
+            FontChoice::VisiaPro => Some(include_bytes!("../res/VisiaPro-BoldOutline.ttf")),
+            _ => None,
+        };
+
+        StyleFont {
+            fill: load_ttf_font_from_bytes(fill).unwrap(),
+            outline: outline.map(|outline| load_ttf_font_from_bytes(outline).unwrap()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct StyleFont {
+    pub fill: Font,
+    pub outline: Option<Font>,
+}
+
+/// All bundled fonts, loaded once at startup so switching between them is instant
+pub struct Fonts(HashMap<FontChoice, StyleFont>);
+
+impl Fonts {
+    pub fn load() -> Self {
+        Self(
+            FontChoice::ALL
+                .iter()
+                .map(|choice| (*choice, choice.load()))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, choice: FontChoice) -> StyleFont {
+        self.0[&choice]
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Style {
+    pub font: FontChoice,
+    pub circle_radius: f32,
+    pub circle_thickness: f32,
+    pub line_thickness: f32,
+    pub text_size: f32,
+    /// Arrowheads along the route showing the driving direction
+    pub arrows: bool,
+    /// Leg number, distance and class at the middle of each leg
+    pub leg_labels: bool,
+    /// Draw legs along the estimated driving path when the map has a road graph
+    pub road_paths: bool,
+    pub checkpoint_color: Rgba,
+    pub route_color: Rgba,
+    pub text_color: Rgba,
+    pub text_outline_color: Rgba,
+    pub tryhisuojaus_color: Rgba,
+    pub tryhisuojaus_route_color: Rgba,
+    pub tryhisuojaus_text_color: Rgba,
+    pub tryhisuojaus_text_outline_color: Rgba,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            font: FontChoice::VisiaPro,
+            circle_radius: 30.0,
+            circle_thickness: 5.0,
+            line_thickness: 7.0,
+            text_size: 100.0,
+            arrows: true,
+            leg_labels: false,
+            road_paths: true,
+            checkpoint_color: [255, 0, 0, 255],
+            route_color: [255, 0, 0, 100],
+            text_color: [255, 255, 255, 255],
+            text_outline_color: [0, 0, 0, 255],
+            tryhisuojaus_color: [0, 0, 255, 255],
+            tryhisuojaus_route_color: [0, 0, 255, 100],
+            tryhisuojaus_text_color: [0, 0, 255, 255],
+            tryhisuojaus_text_outline_color: [255, 255, 255, 255],
+        }
+    }
+}
+
+impl Style {
+    /// Editor for all style values
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Font")
+            .selected_text(self.font.name())
+            .show_ui(ui, |ui| {
+                for choice in FontChoice::ALL {
+                    ui.selectable_value(&mut self.font, *choice, choice.name());
+                }
+            });
+
+        ui.separator();
+
+        ui.add(egui::Slider::new(&mut self.circle_radius, 5.0..=100.0).text("Circle radius"));
+        ui.add(egui::Slider::new(&mut self.circle_thickness, 1.0..=30.0).text("Circle thickness"));
+        ui.add(egui::Slider::new(&mut self.line_thickness, 1.0..=30.0).text("Line thickness"));
+        ui.add(egui::Slider::new(&mut self.text_size, 20.0..=200.0).text("Text size"));
+        ui.checkbox(&mut self.arrows, "Direction arrows");
+        ui.checkbox(&mut self.leg_labels, "Leg labels");
+        ui.checkbox(&mut self.road_paths, "Road paths")
+            .on_hover_text("Only used when the map has a road graph");
+
+        ui.separator();
+
+        egui::Grid::new("style_colors").show(ui, |ui| {
+            for (label, rgba) in [
+                ("Checkpoint", &mut self.checkpoint_color),
+                ("Route", &mut self.route_color),
+                ("Text", &mut self.text_color),
+                ("Text outline", &mut self.text_outline_color),
+                ("Tryhisuojaus checkpoint", &mut self.tryhisuojaus_color),
+                ("Tryhisuojaus route", &mut self.tryhisuojaus_route_color),
+                ("Tryhisuojaus text", &mut self.tryhisuojaus_text_color),
+                (
+                    "Tryhisuojaus text outline",
+                    &mut self.tryhisuojaus_text_outline_color,
+                ),
+            ] {
+                ui.label(label);
+                ui.color_edit_button_srgba_unmultiplied(rgba);
+                ui.end_row();
+            }
+        });
+    }
+}
@@ -0,0 +1,307 @@
+//! Land, water and restricted areas of a map, read from a mask image drawn over the map.
+//!
+//! Mostly blue pixels of the mask are water and mostly red ones restricted, everything else,
+//! transparent pixels included, is land. The mask is stretched over the map, so it can be
+//! smaller than the map image as long as it has the same aspect ratio.
+
+use std::{fs, path::Path};
+
+use egui_macroquad::egui;
+use macroquad::math::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::{list_edit, Point};
+
+/// How much a channel has to exceed the others for a pixel to count as water or restricted
+const DOMINANCE: u8 = 64;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Terrain {
+    Land,
+    Water,
+    Restricted,
+}
+
+impl Terrain {
+    fn classify([r, g, b, a]: [u8; 4]) -> Self {
+        if a < 128 {
+            Terrain::Land
+        } else if b.saturating_sub(r.max(g)) >= DOMINANCE {
+            Terrain::Water
+        } else if r.saturating_sub(g.max(b)) >= DOMINANCE {
+            Terrain::Restricted
+        } else {
+            Terrain::Land
+        }
+    }
+}
+
+/// What a leg passes through on its way
+#[derive(Clone, Copy, Default)]
+pub struct Crossing {
+    /// Longest stretch of water in map pixels
+    pub water: f32,
+    /// The leg starts or ends in water
+    pub water_end: bool,
+    pub restricted: bool,
+}
+
+pub struct TerrainMask {
+    width: u32,
+    height: u32,
+    terrain: Vec<Terrain>,
+}
+
+impl TerrainMask {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data =
+            fs::read(path).map_err(|why| format!("Failed to read {}: {}", path.display(), why))?;
+        let image = image::load_from_memory(&data)
+            .map_err(|why| format!("Failed to decode {}: {}", path.display(), why))?
+            .into_rgba8();
+
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            terrain: image
+                .pixels()
+                .map(|pixel| Terrain::classify(pixel.0))
+                .collect(),
+        })
+    }
+
+    /// Terrain at a point of a map of `map_size` pixels, land outside the map
+    pub fn at(&self, point: Point, map_size: Vec2) -> Terrain {
+        let x = point.x / map_size.x * self.width as f32;
+        let y = point.y / map_size.y * self.height as f32;
+
+        if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+            return Terrain::Land;
+        }
+
+        self.terrain[y as usize * self.width as usize + x as usize]
+    }
+
+    /// What driving along `path` passes through, sampled about once per mask pixel
+    pub fn crossing(&self, path: &[Point], map_size: Vec2) -> Crossing {
+        let step = (map_size.x / self.width as f32).min(map_size.y / self.height as f32);
+        let mut crossing = Crossing::default();
+        let mut water = 0.0;
+
+        for pair in path.windows(2) {
+            let from = Vec2::from(pair[0]);
+            let to = Vec2::from(pair[1]);
+            let length = from.distance(to);
+            let samples = (length / step).ceil().max(1.0) as usize;
+
+            for i in 0..samples {
+                let point = from.lerp(to, i as f32 / samples as f32);
+                match self.at(Point::new(point.x, point.y), map_size) {
+                    Terrain::Land => water = 0.0,
+                    Terrain::Water => {
+                        water += length / samples as f32;
+                        crossing.water = crossing.water.max(water);
+                    }
+                    Terrain::Restricted => {
+                        water = 0.0;
+                        crossing.restricted = true;
+                    }
+                }
+            }
+        }
+
+        // The samples stop short of the end of each segment, so check the very end too
+        for end in [path.first(), path.last()].into_iter().flatten() {
+            match self.at(*end, map_size) {
+                Terrain::Land => (),
+                Terrain::Water => crossing.water_end = true,
+                Terrain::Restricted => crossing.restricted = true,
+            }
+        }
+
+        crossing
+    }
+}
+
+/// What to do with legs that have to cross water
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum WaterLegs {
+    /// Keep them as they are
+    Allow,
+    /// Generate races without them
+    Avoid,
+    /// Give them one of the water classes
+    WaterClasses,
+}
+
+impl WaterLegs {
+    pub const ALL: &'static [WaterLegs] =
+        &[WaterLegs::Allow, WaterLegs::Avoid, WaterLegs::WaterClasses];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WaterLegs::Allow => "Allow",
+            WaterLegs::Avoid => "Avoid",
+            WaterLegs::WaterClasses => "Use boat or air classes",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TerrainConfig {
+    pub water_legs: WaterLegs,
+    /// Keep legs from passing through restricted areas
+    pub avoid_restricted: bool,
+    /// Shortest stretch of water in map pixels that counts as crossing it, so rivers and
+    /// canals with bridges don't
+    pub min_crossing: f32,
+    pub water_classes: Vec<String>,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            water_legs: WaterLegs::Allow,
+            avoid_restricted: false,
+            min_crossing: 20.0,
+            water_classes: ["Boats", "Planes", "Helicopters"]
+                .into_iter()
+                .map(|class| class.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl TerrainConfig {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Only used when the map has a terrain mask.");
+        egui::ComboBox::from_label("Legs crossing water")
+            .selected_text(self.water_legs.name())
+            .show_ui(ui, |ui| {
+                for mode in WaterLegs::ALL {
+                    ui.selectable_value(&mut self.water_legs, *mode, mode.name());
+                }
+            });
+        ui.checkbox(&mut self.avoid_restricted, "Avoid restricted areas");
+        ui.add(
+            egui::Slider::new(&mut self.min_crossing, 0.0..=200.0).text("Shortest water crossing"),
+        )
+        .on_hover_text("In map pixels, narrower water is assumed to be bridged");
+
+        ui.horizontal(|ui| {
+            ui.label("Water classes");
+            list_edit::comma_separated(
+                ui,
+                "water_classes",
+                &mut self.water_classes,
+                "Classes separated by commas",
+            );
+        });
+    }
+
+    pub fn crosses_water(&self, crossing: &Crossing) -> bool {
+        crossing.water_end || crossing.water > 0.0 && crossing.water >= self.min_crossing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP_SIZE: Vec2 = Vec2::new(1000.0, 500.0);
+
+    /// 10x5 mask over a 1000x500 map, with water in columns 4 and 5 and a restricted pixel
+    /// at (8, 3)
+    fn mask() -> TerrainMask {
+        let mut terrain = vec![Terrain::Land; 50];
+        for y in 0..5 {
+            terrain[y * 10 + 4] = Terrain::Water;
+            terrain[y * 10 + 5] = Terrain::Water;
+        }
+        terrain[3 * 10 + 8] = Terrain::Restricted;
+
+        TerrainMask {
+            width: 10,
+            height: 5,
+            terrain,
+        }
+    }
+
+    #[test]
+    fn classify_colors() {
+        assert!(Terrain::classify([0, 0, 255, 255]) == Terrain::Water);
+        assert!(Terrain::classify([30, 90, 200, 255]) == Terrain::Water);
+        assert!(Terrain::classify([255, 0, 0, 255]) == Terrain::Restricted);
+        assert!(Terrain::classify([255, 255, 255, 255]) == Terrain::Land);
+        assert!(Terrain::classify([0, 0, 0, 255]) == Terrain::Land);
+        // Not blue enough
+        assert!(Terrain::classify([100, 100, 150, 255]) == Terrain::Land);
+        assert!(Terrain::classify([0, 0, 255, 0]) == Terrain::Land);
+    }
+
+    #[test]
+    fn mask_is_stretched_over_the_map() {
+        let mask = mask();
+
+        assert!(mask.at(Point::new(450.0, 250.0), MAP_SIZE) == Terrain::Water);
+        assert!(mask.at(Point::new(399.0, 250.0), MAP_SIZE) == Terrain::Land);
+        assert!(mask.at(Point::new(850.0, 350.0), MAP_SIZE) == Terrain::Restricted);
+        assert!(mask.at(Point::new(850.0, 250.0), MAP_SIZE) == Terrain::Land);
+        // Outside the map
+        assert!(mask.at(Point::new(450.0, -1.0), MAP_SIZE) == Terrain::Land);
+        assert!(mask.at(Point::new(1000.0, 0.0), MAP_SIZE) == Terrain::Land);
+    }
+
+    #[test]
+    fn leg_across_water() {
+        let mask = mask();
+        let config = TerrainConfig::default();
+
+        let crossing = mask.crossing(&[Point::new(50.0, 50.0), Point::new(950.0, 50.0)], MAP_SIZE);
+        assert!((crossing.water - 200.0).abs() < 1.0);
+        assert!(!crossing.water_end && !crossing.restricted);
+        assert!(config.crosses_water(&crossing));
+
+        let crossing = mask.crossing(&[Point::new(50.0, 50.0), Point::new(350.0, 50.0)], MAP_SIZE);
+        assert_eq!(crossing.water, 0.0);
+        assert!(!config.crosses_water(&crossing));
+    }
+
+    #[test]
+    fn narrow_water_is_bridged() {
+        let mask = mask();
+        let crossing = mask.crossing(&[Point::new(50.0, 50.0), Point::new(950.0, 50.0)], MAP_SIZE);
+
+        let narrow = TerrainConfig {
+            min_crossing: 150.0,
+            ..Default::default()
+        };
+        assert!(narrow.crosses_water(&crossing));
+        let wide = TerrainConfig {
+            min_crossing: 250.0,
+            ..Default::default()
+        };
+        assert!(!wide.crosses_water(&crossing));
+    }
+
+    #[test]
+    fn ends_are_checked() {
+        let mask = mask();
+        let config = TerrainConfig {
+            min_crossing: 1000.0,
+            ..Default::default()
+        };
+
+        let crossing = mask.crossing(
+            &[Point::new(50.0, 350.0), Point::new(850.0, 350.0)],
+            MAP_SIZE,
+        );
+        assert!(crossing.restricted);
+
+        // Ending just inside the water, too short a stretch to count as crossing it
+        let crossing = mask.crossing(&[Point::new(50.0, 50.0), Point::new(401.0, 50.0)], MAP_SIZE);
+        assert!(crossing.water_end);
+        assert!(config.crosses_water(&crossing));
+    }
+}
@@ -0,0 +1,180 @@
+use egui_macroquad::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{format_distance, maps::MapConfig, roads::RoadGraph, Race};
+
+/// Templates for the text description of a race, meant to be pasted in chat.
+///
+/// Each template has its own set of `{placeholders}`, listed next to it in the editor.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TextTemplates {
+    /// `{seed}`, `{date}`, `{total}`
+    pub header: String,
+    /// `{n}`, `{name}`, `{class}`, `{distance}`
+    pub checkpoint: String,
+    /// `{n}`, `{name}`
+    pub goal: String,
+    /// `{n}`, `{name}`, `{from}`, `{to}`
+    pub tryhisuojaus: String,
+    /// `{player}`, `{assigned}`
+    pub assignment: String,
+}
+
+impl Default for TextTemplates {
+    fn default() -> Self {
+        Self {
+            header: "**Race {seed}** ({total})".to_string(),
+            checkpoint: "{n}. {name}: **{class}** ({distance})".to_string(),
+            goal: "{n}. {name}: **Goal**".to_string(),
+            tryhisuojaus: "- Tryhisuojaus {n} between {from} and {to}: {name}".to_string(),
+            assignment: "- {player}: {assigned}".to_string(),
+        }
+    }
+}
+
+impl TextTemplates {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("text_templates").show(ui, |ui| {
+            for (label, template, placeholders) in [
+                ("Header", &mut self.header, "{seed}, {date}, {total}"),
+                (
+                    "Checkpoint",
+                    &mut self.checkpoint,
+                    "{n}, {name}, {class}, {distance}",
+                ),
+                ("Goal", &mut self.goal, "{n}, {name}"),
+                (
+                    "Tryhisuojaus",
+                    &mut self.tryhisuojaus,
+                    "{n}, {name}, {from}, {to}",
+                ),
+                ("Assignment", &mut self.assignment, "{player}, {assigned}"),
+            ] {
+                ui.label(label);
+                ui.text_edit_singleline(template)
+                    .on_hover_text(placeholders);
+                ui.end_row();
+            }
+        });
+
+        if ui.button("Reset templates").clicked() {
+            *self = Self::default();
+        }
+    }
+
+    /// Markdown description of the race
+    pub fn render(&self, race: &Race, map: &MapConfig, roads: Option<&RoadGraph>) -> String {
+        let checkpoint_name = |i: usize| {
+            map.point_name(race.checkpoints[i].0)
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| format!("Checkpoint {}", i + 1))
+        };
+
+        let mut lines = vec![fill(
+            &self.header,
+            &[
+                ("seed", race.seed.to_string()),
+                ("date", race.generated_at.format("%Y-%m-%d").to_string()),
+                (
+                    "total",
+                    format_distance(race.length(roads) * map.meters_per_pixel),
+                ),
+            ],
+        )];
+
+        for (i, (_, class)) in race.checkpoints.iter().enumerate() {
+            lines.push(match class {
+                Some(class) if i < race.legs() => fill(
+                    &self.checkpoint,
+                    &[
+                        ("n", (i + 1).to_string()),
+                        ("name", checkpoint_name(i)),
+                        ("class", class.clone()),
+                        (
+                            "distance",
+                            format_distance(race.leg_length(i, roads) * map.meters_per_pixel),
+                        ),
+                    ],
+                ),
+                _ => fill(
+                    &self.goal,
+                    &[("n", (i + 1).to_string()), ("name", checkpoint_name(i))],
+                ),
+            });
+        }
+
+        for (i, (index, point)) in race.tryhisuojaus.iter().enumerate() {
+            lines.push(fill(
+                &self.tryhisuojaus,
+                &[
+                    ("n", (i + 1).to_string()),
+                    (
+                        "name",
+                        map.point_name(*point)
+                            .map(|name| name.to_string())
+                            .unwrap_or_else(|| format!("Tryhisuojaus {}", i + 1)),
+                    ),
+                    ("from", (index + 1).to_string()),
+                    ("to", (index + 2).to_string()),
+                ],
+            ));
+        }
+
+        for (player, assigned) in &race.assignments {
+            lines.push(fill(
+                &self.assignment,
+                &[
+                    ("player", player.clone()),
+                    ("assigned", assigned.join(" / ")),
+                ],
+            ));
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Markdown markers dropped around empty values, `****` would show up as is in chat
+const MARKERS: &[&str] = &["**", "__", "*", "_", "`"];
+
+fn fill(template: &str, values: &[(&str, String)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |text, (key, value)| {
+            let placeholder = format!("{{{}}}", key);
+            let text = if value.is_empty() {
+                MARKERS.iter().fold(text, |text, marker| {
+                    text.replace(&format!("{}{}{}", marker, placeholder, marker), "")
+                })
+            } else {
+                text
+            };
+            text.replace(&placeholder, value)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_values_drop_their_markers() {
+        let template = "{n}. {name}: **{class}** ({distance})";
+        let values = |class: &str| {
+            [
+                ("n", "1".to_string()),
+                ("name", "Airport".to_string()),
+                ("class", class.to_string()),
+                ("distance", "2.1 km".to_string()),
+            ]
+        };
+
+        assert_eq!(
+            fill(template, &values("Super")),
+            "1. Airport: **Super** (2.1 km)"
+        );
+        assert_eq!(fill(template, &values("")), "1. Airport:  (2.1 km)");
+        assert_eq!(fill("_{class}_ `{class}`", &values("")), " ");
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use egui_macroquad::egui;
+use macroquad::rand;
+use serde::{Deserialize, Serialize};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest message content Discord accepts, in characters
+const MAX_CONTENT: usize = 2000;
+
+/// Where "Post" sends races, Discord webhooks and anything accepting the same format
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Name shown instead of the webhook's own, if set
+    pub username: String,
+}
+
+impl WebhookConfig {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("webhook_settings").show(ui, |ui| {
+            ui.label("URL");
+            ui.text_edit_singleline(&mut self.url);
+            ui.end_row();
+
+            ui.label("Username");
+            ui.add(egui::TextEdit::singleline(&mut self.username).hint_text("Webhook default"));
+            ui.end_row();
+        });
+    }
+
+    /// Send the text and image in the background, the result arrives on the returned channel
+    pub fn post(
+        &self,
+        text: String,
+        image: Vec<u8>,
+        filename: String,
+        content_type: &'static str,
+    ) -> Receiver<Result<(), String>> {
+        let (sender, receiver) = mpsc::channel();
+        let url = self.url.trim().to_string();
+        let payload = self.payload(text);
+        let boundary = boundary(&[payload.as_bytes(), &image]);
+        let body = multipart_body(&boundary, &payload, &image, &filename, content_type);
+
+        thread::spawn(move || {
+            let result = ureq::AgentBuilder::new()
+                .timeout(TIMEOUT)
+                .build()
+                .post(&url)
+                .set(
+                    "Content-Type",
+                    &format!("multipart/form-data; boundary={}", boundary),
+                )
+                .send_bytes(&body);
+
+            let _ = sender.send(match result {
+                Ok(_) => Ok(()),
+                Err(ureq::Error::Status(code, response)) => Err(format!(
+                    "Webhook answered {}: {}",
+                    code,
+                    response.into_string().unwrap_or_default().trim()
+                )),
+                Err(why) => Err(format!("Could not reach webhook: {}", why)),
+            });
+        });
+
+        receiver
+    }
+
+    /// JSON part of the message, the image is attached as the first file
+    fn payload(&self, text: String) -> String {
+        let mut payload = serde_json::json!({ "content": truncate(text) });
+        if !self.username.is_empty() {
+            payload["username"] = self.username.clone().into();
+        }
+        payload.to_string()
+    }
+}
+
+/// Cut `text` down to the content limit, at the end of a line if there is one
+fn truncate(text: String) -> String {
+    if text.chars().count() <= MAX_CONTENT {
+        return text;
+    }
+
+    // Leave room for the ellipsis
+    let end = text
+        .char_indices()
+        .nth(MAX_CONTENT - 1)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    let text = &text[..end];
+    let text = text.rfind('\n').map_or(text, |newline| &text[..newline]);
+    format!("{}\u{2026}", text)
+}
+
+/// Random multipart boundary that doesn't occur in any of the `parts`
+fn boundary(parts: &[&[u8]]) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or_default();
+
+    loop {
+        let boundary = format!(
+            "gtav-race-gen-{:08x}{:08x}{:08x}",
+            nanos,
+            rand::rand(),
+            rand::rand()
+        );
+        let occurs = parts.iter().any(|part| {
+            part.windows(boundary.len())
+                .any(|window| window == boundary.as_bytes())
+        });
+        if !occurs {
+            return boundary;
+        }
+    }
+}
+
+/// `multipart/form-data` with `payload_json` and `files[0]`, as Discord expects
+fn multipart_body(
+    boundary: &str,
+    payload: &str,
+    image: &[u8],
+    filename: &str,
+    content_type: &str,
+) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    body.extend(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"payload_json\"\r\nContent-Type: application/json\r\n\r\n{}\r\n",
+            boundary, payload
+        )
+        .as_bytes(),
+    );
+    body.extend(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"files[0]\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+            boundary,
+            filename.replace('"', ""),
+            content_type
+        )
+        .as_bytes(),
+    );
+    body.extend(image);
+    body.extend(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posts_multipart_form() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let config = WebhookConfig {
+            url: format!("http://127.0.0.1:{}/webhook", port),
+            username: "Race bot".to_string(),
+        };
+        let image = vec![0x89, b'P', b'N', b'G', 0, 1, 2, 3];
+
+        let result = config.post(
+            "**Race**".to_string(),
+            image.clone(),
+            "race.png".to_string(),
+            "image/png",
+        );
+
+        let mut request = server.recv_timeout(TIMEOUT).unwrap().unwrap();
+        assert_eq!(request.url(), "/webhook");
+        let content_type = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Content-Type"))
+            .unwrap()
+            .value
+            .to_string();
+        let boundary = content_type
+            .strip_prefix("multipart/form-data; boundary=")
+            .unwrap()
+            .to_string();
+        let mut body = Vec::new();
+        request.as_reader().read_to_end(&mut body).unwrap();
+        request.respond(tiny_http::Response::empty(204)).unwrap();
+
+        // Preamble, the two parts and the closing delimiter
+        let delimiter = format!("--{}", boundary);
+        let body = String::from_utf8_lossy(&body).into_owned();
+        let parts = body.split(&delimiter).collect::<Vec<_>>();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[0], "");
+        assert_eq!(parts[3], "--\r\n");
+
+        let (headers, json) = parts[1].split_once("\r\n\r\n").unwrap();
+        assert!(headers.contains("name=\"payload_json\""));
+        assert!(headers.contains("Content-Type: application/json"));
+        let json: serde_json::Value = serde_json::from_str(json.trim_end()).unwrap();
+        assert_eq!(json["content"], "**Race**");
+        assert_eq!(json["username"], "Race bot");
+
+        let (headers, file) = parts[2].split_once("\r\n\r\n").unwrap();
+        assert!(headers.contains("name=\"files[0]\"; filename=\"race.png\""));
+        assert!(headers.contains("Content-Type: image/png"));
+        assert_eq!(
+            file.strip_suffix("\r\n").unwrap(),
+            String::from_utf8_lossy(&image)
+        );
+
+        assert!(result.recv_timeout(TIMEOUT).unwrap().is_ok());
+    }
+
+    #[test]
+    fn error_status_is_reported() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let config = WebhookConfig {
+            url: format!("http://127.0.0.1:{}", port),
+            ..Default::default()
+        };
+
+        let result = config.post(
+            String::new(),
+            Vec::new(),
+            "race.png".to_string(),
+            "image/png",
+        );
+        let request = server.recv_timeout(TIMEOUT).unwrap().unwrap();
+        request
+            .respond(tiny_http::Response::from_string("Bad payload").with_status_code(400))
+            .unwrap();
+
+        let error = result.recv_timeout(TIMEOUT).unwrap().unwrap_err();
+        assert!(error.contains("400"));
+        assert!(error.contains("Bad payload"));
+    }
+
+    #[test]
+    fn long_content_is_truncated() {
+        let line = "Checkpoint with a long name: Sports\n";
+        let text = line.repeat(100);
+
+        let truncated = truncate(text.clone());
+        assert!(truncated.chars().count() <= MAX_CONTENT);
+        assert!(truncated.ends_with("Sports\u{2026}"));
+        assert!(text.starts_with(truncated.trim_end_matches('\u{2026}')));
+
+        assert_eq!(truncate("ä".repeat(MAX_CONTENT)), "ä".repeat(MAX_CONTENT));
+        let cut = truncate("ä".repeat(MAX_CONTENT + 1));
+        assert_eq!(cut.chars().count(), MAX_CONTENT);
+    }
+}